@@ -0,0 +1,107 @@
+use std::{fs::OpenOptions, io};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+use synacor_vm::VM;
+
+const ITERATIONS: u32 = 10_000;
+
+// set r0 0
+// loop: add r0 r0 1
+//       eq r1 r0 ITERATIONS
+//       jf r1 loop
+// halt
+const TIGHT_LOOP: [u32; 15] = [
+    1, 32768, 0, //
+    9, 32768, 32768, 1, //
+    4, 32769, 32768, ITERATIONS, //
+    8, 32769, 3, //
+    0,
+];
+
+fn tight_loop(c: &mut Criterion) {
+    let program = TIGHT_LOOP
+        .iter()
+        .flat_map(|&word| (word as u16).to_le_bytes())
+        .collect::<Vec<u8>>();
+
+    let vm = VM::load_program_from_reader(io::empty(), io::sink(), &program[..]).unwrap();
+
+    let mut group = c.benchmark_group("interpreter");
+    group.throughput(Throughput::Elements(u64::from(1 + 3 * ITERATIONS + 1)));
+    group.bench_function("tight_loop", |b| {
+        b.iter_batched_ref(
+            || {
+                let mut copy =
+                    VM::load_program_from_reader(io::empty(), io::sink(), &[][..]).unwrap();
+                copy.memory = vm.memory;
+                copy
+            },
+            |vm| vm.run_to_halt().unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("tight_loop_cached", |b| {
+        b.iter_batched_ref(
+            || {
+                let mut copy =
+                    VM::load_program_from_reader(io::empty(), io::sink(), &[][..]).unwrap();
+                copy.memory = vm.memory;
+                copy.enable_decode_cache();
+                copy
+            },
+            |vm| vm.run_to_halt().unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn construction(c: &mut Criterion) {
+    let program = include_bytes!("../src/challenge.bin");
+
+    let mut snapshot = Vec::new();
+    VM::load_program(io::empty(), io::sink(), program)
+        .save_snapshot(&mut snapshot)
+        .unwrap();
+
+    let mut group = c.benchmark_group("construction");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("load_program", |b| {
+        b.iter(|| VM::load_program(io::empty(), io::sink(), program))
+    });
+    group.bench_function("load_snapshot", |b| {
+        b.iter(|| VM::load_snapshot(io::empty(), io::sink(), &snapshot[..]).unwrap())
+    });
+    group.finish();
+}
+
+// a run of challenge.bin up to its first prompt, which prints some five
+// hundred characters, into a file whose writes are real syscalls
+fn output(c: &mut Criterion) {
+    let program = include_bytes!("../src/challenge.bin");
+    let null = if cfg!(windows) { "NUL" } else { "/dev/null" };
+
+    let mut group = c.benchmark_group("output");
+    for buffered in [false, true] {
+        let name = if buffered { "buffered" } else { "unbuffered" };
+        group.bench_function(name, |b| {
+            b.iter_batched_ref(
+                || {
+                    let null = OpenOptions::new().write(true).open(null).unwrap();
+                    let mut vm = VM::load_program(io::empty(), null, program);
+                    if buffered {
+                        vm.enable_output_buffering();
+                    }
+                    vm
+                },
+                |vm| vm.run_to_halt().unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, tight_loop, construction, output);
+criterion_main!(benches);
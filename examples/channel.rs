@@ -0,0 +1,35 @@
+use std::{io, sync::mpsc, thread, time::Duration};
+
+use eyre::Result;
+
+use synacor_vm::{ChannelReader, VM};
+
+fn main() -> Result<()> {
+    let (sender, receiver) = mpsc::channel();
+
+    // Another thread plays the part of whoever is sending us commands, e.g. a
+    // chat bot relaying its users' messages
+    let commands = thread::spawn(move || {
+        for command in ["look", "take tablet", "use tablet"].iter() {
+            thread::sleep(Duration::from_millis(100));
+            for &byte in command.as_bytes().iter().chain(b"\n") {
+                if sender.send(byte).is_err() {
+                    return;
+                }
+            }
+        }
+
+        // Dropping the sender closes the channel, which the VM sees as EOF
+    });
+
+    let mut vm = VM::load_program(
+        ChannelReader::new(receiver),
+        io::stdout(),
+        include_bytes!("../src/challenge.bin"),
+    );
+    vm.run_to_halt()?;
+
+    commands.join().unwrap();
+
+    Ok(())
+}
@@ -0,0 +1,51 @@
+//! Play the opening of the challenge by feeding it commands one at a time,
+//! printing where each one leads. It's deterministic, so the output is the
+//! same every run.
+
+use eyre::{eyre, Result};
+
+use synacor_vm::{Room, VM};
+
+const COMMANDS: [&str; 7] = [
+    "take tablet",
+    "use tablet",
+    "doorway",
+    "north",
+    "north",
+    "bridge",
+    "continue",
+];
+
+fn print_room(room: &Room) {
+    println!("== {} ==", room.title);
+    if !room.items.is_empty() {
+        println!("items: {}", room.items.join(", "));
+    }
+    println!("exits: {}", room.exits.join(", "));
+}
+
+fn main() -> Result<()> {
+    // Both input and output are kept in memory: commands are queued up as
+    // input, and output is scanned for rooms
+    let mut vm = VM::in_memory(include_bytes!("../src/challenge.bin"))?;
+
+    // The self-test and the welcome message come before the first room
+    let (_, room) = vm.cycle_until_next_room()?;
+    print_room(&room.ok_or_else(|| eyre!("the game didn't start in a room"))?);
+
+    for command in COMMANDS.iter() {
+        println!("\n> {}", command);
+        // Commands like `take` print a message and then prompt again without
+        // describing a room, in which case the prompt ends up in the prelude
+        let (prelude, room) = vm.send_command(command)?;
+        let prelude = prelude.trim().trim_end_matches("What do you do?").trim();
+        if !prelude.is_empty() {
+            println!("{}", prelude);
+        }
+        if let Some(room) = room {
+            print_room(&room);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use std::{env, fs::File, io};
+
+use eyre::{bail, eyre, Result};
+
+use synacor_vm::{run_file, run_snapshot_file, FlushPolicy, Trace, VM};
+
+fn main() -> Result<()> {
+    // usage: run [--snapshot] [--trace <file>] [path, or - for stdin]
+    // snapshots are told apart from programs by themselves, --snapshot is
+    // only there to insist on one
+    let mut snapshot = false;
+    let mut trace = None;
+    let mut path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--snapshot" => snapshot = true,
+            "--trace" => {
+                let file = args
+                    .next()
+                    .ok_or_else(|| eyre!("--trace requires a file"))?;
+                trace = Some(Trace::new(File::create(file)?));
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => bail!(eyre!("unexpected argument {:?}", arg)),
+        }
+    }
+
+    // not locked, as the program itself may be coming in on stdin
+    let input = io::stdin();
+    let output = io::stdout().lock();
+
+    let mut vm = match path {
+        Some(path) if snapshot => run_snapshot_file(path, input, output)?,
+        Some(path) => run_file(path, input, output)?,
+        None if snapshot => bail!(eyre!("--snapshot requires a path")),
+        None => VM::load_program(input, output, include_bytes!("../challenge.bin")),
+    };
+
+    // someone may well be typing along, so show them each line as it's done,
+    // but no sooner
+    vm.flush_policy = FlushPolicy::EveryLine;
+    vm.enable_output_buffering();
+
+    match trace {
+        Some(mut trace) => {
+            // keep whatever was traced even if the run fails
+            let result = vm.run_to_halt_with(|vm| Ok(trace.record(vm)?));
+            trace.into_inner()?;
+            result
+        }
+        None => vm.run_to_halt(),
+    }
+}
@@ -0,0 +1,56 @@
+use std::{env, io};
+
+use eyre::{bail, eyre, Result};
+
+use synacor_vm::{run_snapshot_file, Register};
+
+// prints one line per difference, prefixed by what differs, so that the
+// output can be grepped
+fn main() -> Result<()> {
+    // usage: snapdiff <before> <after>
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let (before, after) = match &args[..] {
+        [before, after] => (before, after),
+        _ => bail!(eyre!("usage: snapdiff <before> <after>")),
+    };
+
+    let before = run_snapshot_file(before, io::empty(), io::sink())?;
+    let after = run_snapshot_file(after, io::empty(), io::sink())?;
+
+    if before.pc != after.pc {
+        println!("pc {:#06x} -> {:#06x}", before.pc, after.pc);
+    }
+
+    for register in Register::ALL {
+        let (old, new) = (before.register(register), after.register(register));
+        if old != new {
+            println!("{} {} -> {}", register, old, new);
+        }
+    }
+
+    let (old, new) = (&before.stack, &after.stack);
+    if old.len() != new.len() {
+        println!("stack depth {} -> {}", old.len(), new.len());
+    }
+    for (idx, (old, new)) in old.iter().zip(new).enumerate() {
+        if old != new {
+            println!("stack[{}] {} -> {}", idx, old, new);
+        }
+    }
+    let common = old.len().min(new.len());
+    for (idx, value) in old.iter().enumerate().skip(common) {
+        println!("stack[{}] - {}", idx, value);
+    }
+    for (idx, value) in new.iter().enumerate().skip(common) {
+        println!("stack[{}] + {}", idx, value);
+    }
+
+    let changes = before.memory.iter().zip(after.memory.iter()).enumerate();
+    for (address, (old, new)) in changes {
+        if old != new {
+            println!("mem {:#06x} {} -> {}", address, old, new);
+        }
+    }
+
+    Ok(())
+}
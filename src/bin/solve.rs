@@ -0,0 +1,201 @@
+use std::{env, io::Cursor};
+
+use eyre::{bail, eyre, Result};
+
+use synacor_vm::{
+    solvers::{
+        coins::solve_coins,
+        stages::{load_stage, save_stage, Stage},
+        teleporter::patch_teleporter_with_progress,
+        twisty,
+        vault::{in_grid, solve_weighted_grid_with_progress},
+        Progress,
+    },
+    Register,
+};
+
+type VM = synacor_vm::VM<Cursor<Vec<u8>>, Cursor<Vec<u8>>>;
+
+/// Each stage, along with the snapshot it starts from and the one it leaves
+/// behind, which is where the next stage starts from.
+const STAGES: [(&str, Stage, Stage); 4] = [
+    ("twisty", Stage::TwistyPassages, Stage::LitLantern),
+    ("coins", Stage::LitLantern, Stage::Teleporter),
+    ("teleporter", Stage::Teleporter, Stage::TeleporterPatched),
+    ("vault", Stage::TeleporterPatched, Stage::Vault),
+];
+
+/// From where the lantern gets lit to the ruins' central hall, picking up
+/// every coin on the way.
+const TO_THE_RUINS: [&str; 23] = [
+    "west",
+    "ladder",
+    "darkness",
+    "continue",
+    "west",
+    "west",
+    "west",
+    "west",
+    "north",
+    "take red coin",
+    "north",
+    "east",
+    "take concave coin",
+    "down",
+    "take corroded coin",
+    "up",
+    "west",
+    "west",
+    "take blue coin",
+    "up",
+    "take shiny coin",
+    "down",
+    "east",
+];
+
+/// From the beach the teleporter lands on to the vault antechamber.
+const TO_THE_VAULT: [&str; 9] = ["north"; 9];
+
+fn heartbeat(progress: Progress) {
+    match progress {
+        Progress::Candidates { tried, total } => {
+            eprintln!("  tried {}/{} candidates", tried, total)
+        }
+        Progress::Rooms(rooms) => eprintln!("  explored {} rooms", rooms),
+    }
+}
+
+// send `commands` one after the other, making sure they lead to `destination`
+fn walk(vm: &mut VM, commands: &[&str], destination: &str) -> Result<()> {
+    let mut title = None;
+    for command in commands {
+        if let Some(room) = vm.send_command(command)?.1 {
+            title = Some(room.title);
+        }
+    }
+
+    if title.as_deref() != Some(destination) {
+        bail!(eyre!(
+            "expected to end up in {:?}, got to {:?} instead",
+            destination,
+            title
+        ));
+    }
+
+    Ok(())
+}
+
+/// Run `stage` on `vm`, which is where the stage before it left off, and
+/// return the VM to leave behind for the next one.
+fn run_stage(
+    stage: &str,
+    mut vm: Box<VM>,
+    codes: &mut Vec<(&'static str, String)>,
+) -> Result<Box<VM>> {
+    match stage {
+        "twisty" => {
+            let (vm, start) = twisty::light_lantern(vm, twisty::in_passages)?;
+
+            for code in
+                twisty::find_codes_with_progress(vm.clone(), start, twisty::in_passages, heartbeat)?
+            {
+                codes.push(("twisty passages", code));
+            }
+
+            Ok(vm)
+        }
+
+        "coins" => {
+            let coins = solve_coins();
+            walk(&mut vm, &TO_THE_RUINS, "Ruins")?;
+            for coin in &coins {
+                vm.send_command(&format!("use {}", coin))?;
+            }
+            walk(&mut vm, &["north", "take teleporter", "look"], "Ruins")?;
+
+            codes.push(("coins", coins.join(", ")));
+            Ok(vm)
+        }
+
+        "teleporter" => {
+            patch_teleporter_with_progress(&mut vm, heartbeat)?;
+
+            codes.push(("teleporter", format!("r7 = {}", vm.register(Register::R7))));
+            Ok(vm)
+        }
+
+        "vault" => {
+            // the teleportation was left halfway through
+            vm.cycle_until_next_room()?;
+            walk(&mut vm, &TO_THE_VAULT, "Vault Antechamber")?;
+
+            codes.push((
+                "vault",
+                solve_weighted_grid_with_progress(vm.clone(), in_grid, heartbeat)?.join(" "),
+            ));
+            Ok(vm)
+        }
+
+        _ => bail!(eyre!("unknown stage {:?}", stage)),
+    }
+}
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    // usage: solve [--from <stage>] [--skip <stage>]...
+    let mut from = STAGES[0].0.to_owned();
+    let mut skip = Vec::new();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg != "--from" && arg != "--skip" {
+            bail!(eyre!("unknown argument {:?}", arg));
+        }
+
+        let value = args
+            .next()
+            .ok_or_else(|| eyre!("missing stage name after {}", arg))?;
+
+        if !STAGES.iter().any(|&(stage, ..)| stage == value) {
+            bail!(eyre!(
+                "unknown stage {:?}, expected one of {:?}",
+                value,
+                STAGES.map(|(stage, ..)| stage)
+            ));
+        }
+
+        if arg == "--from" {
+            from = value;
+        } else {
+            skip.push(value);
+        }
+    }
+
+    let mut codes = Vec::new();
+    // what the last stage to run left behind, if it was the one just before
+    let mut vm = None;
+    for &(stage, starts_from, leaves) in STAGES.iter().skip_while(|&&(stage, ..)| stage != from) {
+        if skip.iter().any(|skipped| skipped == stage) {
+            // the next stage picks up from this one's snapshot instead
+            vm = None;
+            continue;
+        }
+
+        let start = match vm.take() {
+            Some(vm) => vm,
+            None => load_stage(starts_from)?,
+        };
+
+        eprintln!("running stage {}", stage);
+        let end = run_stage(stage, start, &mut codes)?;
+        save_stage(&end, leaves)?;
+        vm = Some(end);
+    }
+
+    for (stage, code) in codes {
+        println!("{:>16}: {}", stage, code);
+    }
+
+    Ok(())
+}
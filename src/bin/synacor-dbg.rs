@@ -0,0 +1,290 @@
+use std::{
+    cmp::Reverse,
+    env, fs,
+    io::{self, BufRead, BufReader, Cursor, Write},
+};
+
+use eyre::{bail, eyre, Result};
+
+use synacor_vm::{
+    cfg, decode, disassemble, run_file, Error, InputEof, Register, StopReason, ADDRESS_SPACE,
+    MAX_VALUE, VM,
+};
+
+type DebugVM = VM<Cursor<Vec<u8>>, io::Stdout>;
+
+const DEFAULT_SNAPSHOT: &str = "snapshot.bin";
+
+const WAITING_FOR_INPUT: &str = "the program is waiting for input, queue some with `input`";
+
+const HELP: &str = "\
+step [n]           run n instructions (default 1), ignoring breakpoints
+next               step, but run a call until it returns
+finish             run until the current function returns
+continue           run until a breakpoint, a halt or the program wants input
+input <text>       queue up a line of input for the program
+break [addr]       set a breakpoint, or list them without an address
+delete <addr>      remove a breakpoint
+regs               show the registers and pc
+stack              show the stack, top last
+mem <addr> [len]   show len words of memory (default 8)
+disas [addr] [n]   disassemble n instructions (default 10) from addr (default pc)
+cfg <addr> [path]  write the control-flow graph from addr as DOT, to stdout by default
+profile            show how many times each opcode has run, most common first
+set r<n> <val>     set a register, or pc
+save [path]        save a snapshot (default snapshot.bin)
+load [path]        load a snapshot (default snapshot.bin)
+quit               leave the debugger
+
+Numbers can be given in decimal or as 0x-prefixed hexadecimal.";
+
+fn parse_number(s: &str) -> Result<u32> {
+    let n = match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    n.map_err(|_| eyre!("{:?} is not a number", s))
+}
+
+fn parse_address(s: &str) -> Result<usize> {
+    let address = parse_number(s)?;
+    if address as usize >= ADDRESS_SPACE {
+        bail!(eyre!("{:#x} is past the end of memory", address));
+    }
+    Ok(address as usize)
+}
+
+fn show_pc(vm: &DebugVM) {
+    match disassemble(&vm.memory, vm.pc).next() {
+        Some((address, Ok(instruction))) => println!("{:#06x}: {}", address, instruction),
+        Some((address, Err(err))) => println!("{:#06x}: {}", address, err),
+        None => println!("pc {:#x} is past the end of memory", vm.pc),
+    }
+}
+
+/// Explain why the program stopped running, if it didn't just run out of
+/// cycles.
+fn report(result: Result<StopReason>, vm: &DebugVM) -> Result<()> {
+    io::stdout().flush()?;
+
+    match result {
+        Ok(StopReason::CycleLimit | StopReason::Stepped | StopReason::OutputMatched) => {}
+        Ok(StopReason::Halted) => println!("the program halted"),
+        Ok(StopReason::Breakpoint(address)) => println!("breakpoint at {:#x}", address),
+        Ok(StopReason::StackDepth(depth)) => println!("the stack is {} entries deep", depth),
+        Ok(StopReason::NeedsInput) => println!("{}", WAITING_FOR_INPUT),
+        Ok(StopReason::NoProgress(address)) => println!("stuck in a loop at {:#x}", address),
+        Err(err) => match err.downcast_ref() {
+            Some(Error::InputExhausted) => println!("{}", WAITING_FOR_INPUT),
+            _ => return Err(err),
+        },
+    }
+
+    show_pc(vm);
+    Ok(())
+}
+
+fn step(vm: &mut DebugVM, n: u64) -> Result<StopReason> {
+    for _ in 0..n {
+        if let Err(err) = vm.cycle() {
+            if let Some(Error::Halt) = err.downcast_ref() {
+                return Ok(StopReason::Halted);
+            }
+
+            bail!(err);
+        }
+    }
+
+    Ok(StopReason::CycleLimit)
+}
+
+/// Run a single command, returning whether it's time to quit.
+fn execute(vm: &mut DebugVM, line: &str) -> Result<bool> {
+    let mut words = line.split_whitespace();
+    let command = match words.next() {
+        Some(command) => command,
+        None => return Ok(false),
+    };
+    let args = words.collect::<Vec<_>>();
+
+    match (command, args.as_slice()) {
+        ("step" | "s", []) => report(step(vm, 1), vm)?,
+        ("step" | "s", [n]) => {
+            let n = parse_number(n)?;
+            report(step(vm, u64::from(n)), vm)?
+        }
+
+        ("next" | "n", []) => report(vm.step_over(), vm)?,
+        ("finish" | "f", []) => report(vm.step_out(), vm)?,
+
+        ("continue" | "c", []) => report(vm.run_for(u64::MAX), vm)?,
+
+        ("input" | "i", _) => {
+            let text = line.trim_start()[command.len()..].trim_start();
+            vm.append_input(text)?;
+            vm.append_input("\n")?;
+        }
+
+        ("break" | "b", []) => {
+            for address in vm.breakpoints() {
+                println!("{:#06x}", address);
+            }
+        }
+        ("break" | "b", [address]) => {
+            let address = parse_address(address)?;
+            vm.add_breakpoint(address);
+            println!("breakpoint set at {:#x}", address);
+        }
+
+        ("delete" | "d", [address]) => {
+            let address = parse_address(address)?;
+            if !vm.remove_breakpoint(address) {
+                bail!(eyre!("there's no breakpoint at {:#x}", address));
+            }
+        }
+
+        ("regs" | "r", []) => {
+            for &register in &Register::ALL {
+                let value = vm.register(register);
+                println!("{} = {:5} ({:#06x})", register, value, value);
+            }
+            println!("pc = {:5} ({:#06x})", vm.pc, vm.pc);
+        }
+
+        ("stack", []) => {
+            if vm.stack.is_empty() {
+                println!("the stack is empty");
+            }
+            for value in &vm.stack {
+                println!("{:5} ({:#06x})", value, value);
+            }
+        }
+
+        ("mem" | "x", [address, rest @ ..]) if rest.len() <= 1 => {
+            let start = parse_address(address)?;
+            let len = rest.first().map_or(Ok(8), |len| parse_number(len))? as usize;
+            let end = (start + len).min(vm.memory.len());
+
+            for (row_start, row) in (start..end).step_by(8).zip(vm.memory[start..end].chunks(8)) {
+                print!("{:#06x}:", row_start);
+                for word in row {
+                    print!(" {:04x}", word);
+                }
+                println!();
+            }
+        }
+
+        ("disas" | "u", _) if args.len() <= 2 => {
+            let start = args
+                .first()
+                .map_or(Ok(vm.pc), |address| parse_address(address))?;
+            let n = args.get(1).map_or(Ok(10), |n| parse_number(n))? as usize;
+
+            for (address, instruction) in disassemble(&vm.memory, start).take(n) {
+                let marker = if address == vm.pc { '>' } else { ' ' };
+                match instruction {
+                    Ok(instruction) => println!("{} {:#06x}: {}", marker, address, instruction),
+                    Err(_) => println!("{} {:#06x}: .word {}", marker, address, vm.memory[address]),
+                }
+            }
+        }
+
+        ("cfg", [address, rest @ ..]) if rest.len() <= 1 => {
+            let dot = cfg(&vm.memory, parse_address(address)?).to_dot();
+            match rest.first() {
+                Some(path) => {
+                    fs::write(path, dot)?;
+                    println!("wrote the graph to {}", path);
+                }
+                None => print!("{}", dot),
+            }
+        }
+
+        ("profile", []) => {
+            let mut histogram = vm.opcode_histogram().into_iter().collect::<Vec<_>>();
+            histogram.sort_by_key(|&(opcode, count)| (Reverse(count), opcode));
+            let total = histogram.iter().map(|&(_, count)| count).sum::<u64>();
+            for (opcode, count) in histogram {
+                let share = count as f64 / total as f64 * 100.0;
+                println!("{:<4} {:>12} {:5.1}%", opcode.name(), count, share);
+            }
+            println!("{} instructions run in the debugger", total);
+        }
+
+        ("set", [target, value]) => {
+            if *target == "pc" {
+                let address = parse_address(value)?;
+                vm.set_pc(address)?;
+                if let Err(err) = decode(&vm.memory, address) {
+                    println!("warning: {}", err);
+                }
+            } else {
+                let value = parse_number(value)?;
+                let register = target
+                    .strip_prefix('r')
+                    .and_then(|n| n.parse().ok())
+                    .and_then(Register::new)
+                    .ok_or_else(|| eyre!("{:?} is not a register", target))?;
+                if value >= MAX_VALUE {
+                    bail!(eyre!("{} doesn't fit in a register", value));
+                }
+                vm.set_register(register, value);
+            }
+        }
+
+        ("save", [] | [_]) => {
+            let path = args.first().copied().unwrap_or(DEFAULT_SNAPSHOT);
+            vm.save_snapshot(fs::File::create(path)?)?;
+            println!("saved to {}", path);
+        }
+
+        ("load", [] | [_]) => {
+            let path = args.first().copied().unwrap_or(DEFAULT_SNAPSHOT);
+            vm.load_snapshot_inplace(BufReader::new(fs::File::open(path)?))?;
+            println!("loaded {}", path);
+            show_pc(vm);
+        }
+
+        ("help" | "h" | "?", []) => println!("{}", HELP),
+
+        ("quit" | "q", []) => return Ok(true),
+
+        _ => bail!(eyre!("can't make sense of {:?}, try `help`", line.trim())),
+    }
+
+    Ok(false)
+}
+
+fn main() -> Result<()> {
+    // usage: synacor-dbg [path to a program or snapshot, or - for stdin]
+    let input = Cursor::new(Vec::new());
+    let output = io::stdout();
+    let mut vm = match env::args().nth(1) {
+        Some(path) => run_file(path, input, output)?,
+        None => VM::load_program(input, output, include_bytes!("../challenge.bin")),
+    };
+    vm.input_eof = InputEof::Error;
+    vm.enable_opcode_histogram();
+
+    show_pc(&vm);
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("(dbg) ");
+        io::stdout().flush()?;
+
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+
+        match execute(&mut vm, &line) {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(err) => println!("error: {}", err),
+        }
+    }
+
+    Ok(())
+}
@@ -1,101 +1,37 @@
-use io::Cursor;
-use std::{collections::HashSet, fs, io};
+use std::{env, path::PathBuf};
 
 use eyre::{bail, eyre, Result};
 
-use synacor_vm::Room;
-
-type VM = synacor_vm::VM<Cursor<Vec<u8>>, Cursor<Vec<u8>>>;
-
-fn find_can(visited: &mut HashSet<String>, mut vm: Box<VM>, room: Room) -> Result<Option<Box<VM>>> {
-    if !room.items.is_empty() {
-        debug_assert!(room.items.len() == 1 && room.items[0] == "can");
-        vm.append_input("take can\nuse can\nuse lantern\n")?;
-        return Ok(Some(vm));
-    }
-
-    for exit in room.exits.into_iter() {
-        if exit == "ladder" {
-            continue;
-        }
-
-        let mut vm = vm.clone();
-        vm.append_input(&exit)?;
-        vm.append_input("\n")?;
-
-        let next_room = vm.cycle_until_next_room()?.1;
-        if let Some(next_room) = next_room {
-            if visited.insert(next_room.description.clone()) {
-                if let Some(can) = find_can(visited, vm, next_room)? {
-                    return Ok(Some(can));
-                }
-            }
-        }
-    }
-
-    Ok(None)
-}
-
-fn walk(visited: &mut HashSet<String>, vm: Box<VM>, room: Room) -> Result<()> {
-    for exit in room.exits.into_iter() {
-        if exit == "ladder" {
-            continue;
-        }
-
-        let mut vm = vm.clone();
-        vm.append_input(&exit)?;
-        vm.append_input("\n")?;
-
-        let (prelude, next_room) = vm.cycle_until_next_room()?;
-
-        let prelude = prelude.trim();
-
-        if prelude.is_empty() {
-            /* do nothing */
-        } else if prelude.starts_with("Chiseled") {
-            eprintln!("{}", prelude);
-        } else {
-            bail!(eyre!("Unknown prelude: {:?}", prelude));
-        }
-
-        if let Some(next_room) = next_room {
-            if visited.insert(next_room.description.clone()) {
-                walk(visited, vm, next_room)?;
-            }
-        }
-    }
-
-    Ok(())
-}
+use synacor_vm::solvers::{
+    stages::{load_stage_from, save_stage_to, Stage},
+    twisty::{find_codes, in_passages, light_lantern},
+};
 
 fn main() -> Result<()> {
     color_eyre::install()?;
 
-    let mut vm = VM::load_snapshot(
-        io::Cursor::new(b"look\n".to_vec()),
-        io::Cursor::new(Vec::new()),
-        fs::File::open("snapshots/00_twistypassages.snapshot.bin")?,
-    )?;
-
-    let start = vm.cycle_until_next_room()?.1.unwrap();
-
-    let mut visited = HashSet::new();
-    let mut vm = find_can(&mut visited, vm, start)?.unwrap();
-
-    // skip taken message
-    vm.cycle_until_next_room()?;
+    // usage: twistypassages [snapshot] [lit lantern snapshot]
+    let mut args = env::args_os().skip(1);
+    let snapshot = args
+        .next()
+        .map_or_else(|| Stage::TwistyPassages.path(), PathBuf::from);
+    let lit_lantern = args
+        .next()
+        .map_or_else(|| Stage::LitLantern.path(), PathBuf::from);
+    if let Some(arg) = args.next() {
+        bail!(eyre!("unexpected argument {:?}", arg));
+    }
 
-    // skip use can message
-    vm.cycle_until_next_room()?;
+    let vm = load_stage_from(snapshot, Stage::TwistyPassages)?;
 
-    // use lantern, for whatever reason, prints the room
-    let start = vm.cycle_until_next_room()?.1.unwrap();
+    let (vm, start) = light_lantern(vm, in_passages)?;
 
-    vm.save_snapshot(fs::File::create("snapshots/01_lit_lantern.snapshot.bin")?)?;
+    save_stage_to(lit_lantern, &vm)?;
 
     // walk to find chiseled code
-    visited.clear();
-    walk(&mut visited, vm, start)?;
+    for code in find_codes(vm, start, in_passages)? {
+        eprintln!("{}", code);
+    }
 
     Ok(())
 }
@@ -0,0 +1,181 @@
+use std::io::{self, Read, Write};
+
+use eyre::{bail, eyre, Result};
+
+use crate::{FlushPolicy, InputEof, InputNewlineMode, VM};
+
+/// A friendlier way to set up a [`VM`] than picking the right constructor
+/// and then poking at its fields.
+///
+/// ```
+/// # use synacor_vm::{InputEof, VmBuilder};
+/// let _vm = VmBuilder::new()
+///     .program(&[19, 0, 65, 0, 0, 0])
+///     .input(std::io::stdin())
+///     .eof_policy(InputEof::Error)
+///     .breakpoint(2)
+///     .build()
+///     .unwrap();
+/// ```
+pub struct VmBuilder<Input, Output> {
+    input: Input,
+    output: Output,
+    program: Option<Vec<u8>>,
+    snapshot: Option<io::Result<Vec<u8>>>,
+    input_eof: InputEof,
+    input_newline: InputNewlineMode,
+    flush_policy: FlushPolicy,
+    history_len: usize,
+    breakpoints: Vec<usize>,
+}
+
+impl VmBuilder<io::Empty, io::Sink> {
+    /// Start with no input, discarding all output.
+    pub fn new() -> Self {
+        Self {
+            input: io::empty(),
+            output: io::sink(),
+            program: None,
+            snapshot: None,
+            input_eof: InputEof::default(),
+            input_newline: InputNewlineMode::default(),
+            flush_policy: FlushPolicy::default(),
+            history_len: 0,
+            breakpoints: Vec::new(),
+        }
+    }
+}
+
+impl Default for VmBuilder<io::Empty, io::Sink> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Input: Read, Output: Write> VmBuilder<Input, Output> {
+    /// Load a little-endian program image, like `challenge.bin`.
+    pub fn program(mut self, program: &[u8]) -> Self {
+        self.program = Some(program.to_owned());
+        self
+    }
+
+    /// Resume from a snapshot. It's only checked by [`VmBuilder::build`].
+    pub fn snapshot(mut self, mut r: impl Read) -> Self {
+        let mut snapshot = Vec::new();
+        self.snapshot = Some(r.read_to_end(&mut snapshot).map(|_| snapshot));
+        self
+    }
+
+    pub fn input<NewInput: Read>(self, input: NewInput) -> VmBuilder<NewInput, Output> {
+        VmBuilder {
+            input,
+            output: self.output,
+            program: self.program,
+            snapshot: self.snapshot,
+            input_eof: self.input_eof,
+            input_newline: self.input_newline,
+            flush_policy: self.flush_policy,
+            history_len: self.history_len,
+            breakpoints: self.breakpoints,
+        }
+    }
+
+    pub fn output<NewOutput: Write>(self, output: NewOutput) -> VmBuilder<Input, NewOutput> {
+        VmBuilder {
+            input: self.input,
+            output,
+            program: self.program,
+            snapshot: self.snapshot,
+            input_eof: self.input_eof,
+            input_newline: self.input_newline,
+            flush_policy: self.flush_policy,
+            history_len: self.history_len,
+            breakpoints: self.breakpoints,
+        }
+    }
+
+    /// What to do when the program wants input and there's none left.
+    pub fn eof_policy(mut self, input_eof: InputEof) -> Self {
+        self.input_eof = input_eof;
+        self
+    }
+
+    /// Whether `in` skips carriage returns.
+    pub fn newline_mode(mut self, input_newline: InputNewlineMode) -> Self {
+        self.input_newline = input_newline;
+        self
+    }
+
+    /// When `out` flushes the output.
+    pub fn flush_policy(mut self, flush_policy: FlushPolicy) -> Self {
+        self.flush_policy = flush_policy;
+        self
+    }
+
+    /// Remember the addresses of the last `len` instructions run.
+    pub fn enable_history(mut self, len: usize) -> Self {
+        self.history_len = len;
+        self
+    }
+
+    pub fn breakpoint(mut self, address: usize) -> Self {
+        self.breakpoints.push(address);
+        self
+    }
+
+    /// Build the VM, making sure exactly one of a program or a snapshot was
+    /// given.
+    pub fn build(self) -> Result<Box<VM<Input, Output>>> {
+        let mut vm = match (self.program, self.snapshot) {
+            (Some(program), None) => {
+                VM::load_program_from_reader(self.input, self.output, &program[..])?
+            }
+            (None, Some(snapshot)) => VM::load_snapshot(self.input, self.output, &snapshot?[..])?,
+            (None, None) => bail!(eyre!("either a program or a snapshot is needed")),
+            (Some(_), Some(_)) => bail!(eyre!("can't load both a program and a snapshot")),
+        };
+
+        vm.input_eof = self.input_eof;
+        vm.input_newline = self.input_newline;
+        vm.flush_policy = self.flush_policy;
+        vm.enable_history(self.history_len);
+        for address in self.breakpoints {
+            vm.add_breakpoint(address);
+        }
+
+        Ok(vm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::StopReason;
+
+    #[test]
+    fn test_build() {
+        // out 'A'; noop; halt
+        let program = [19, 0, 65, 0, 21, 0, 0, 0];
+
+        let mut vm = VmBuilder::new()
+            .program(&program)
+            .output(Cursor::new(Vec::new()))
+            .enable_history(2)
+            .breakpoint(3)
+            .build()
+            .unwrap();
+        assert_eq!(vm.run_for(10).unwrap(), StopReason::Breakpoint(3));
+        assert_eq!(vm.run_for(10).unwrap(), StopReason::Halted);
+        assert_eq!(vm.output.get_ref(), b"A");
+        assert_eq!(vm.history().collect::<Vec<_>>(), [0, 2]);
+
+        assert!(VmBuilder::new().build().is_err());
+        assert!(VmBuilder::new()
+            .program(&program)
+            .snapshot(&[][..])
+            .build()
+            .is_err());
+    }
+}
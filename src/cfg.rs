@@ -0,0 +1,304 @@
+//! Basic-block control-flow graphs, to make sense of routines like the
+//! teleporter's confirmation one.
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    format,
+    string::String,
+    vec,
+    vec::Vec,
+};
+use core::fmt::Write;
+
+use crate::{decode, disassemble, Instruction, Opcode, MAX_VALUE};
+
+/// Where control can go once a [`Block`] is done.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Target {
+    Address(usize),
+
+    /// A jump or call through a register, which can't be followed without
+    /// running the program.
+    Unknown,
+}
+
+/// A run of instructions that's only ever entered at the top and left at the
+/// bottom.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub start: usize,
+    pub instructions: Vec<(usize, Instruction)>,
+
+    /// Empty for blocks that halt, return or run into something that doesn't
+    /// decode.
+    pub successors: Vec<Target>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Graph {
+    entry: usize,
+    blocks: BTreeMap<usize, Block>,
+}
+
+fn branch_target(arg: u32) -> Target {
+    if arg < MAX_VALUE {
+        Target::Address(arg as usize)
+    } else {
+        Target::Unknown
+    }
+}
+
+// where control can go after the instruction at `address`, or `None` if it
+// just carries on with the next one
+fn exits(address: usize, instruction: &Instruction) -> Option<Vec<Target>> {
+    let next = Target::Address(address + instruction.len());
+    let mut targets = match instruction.opcode {
+        Opcode::Halt | Opcode::Ret => vec![],
+        Opcode::Jmp => vec![branch_target(instruction.args[0])],
+        Opcode::Jt | Opcode::Jf => vec![branch_target(instruction.args[1]), next],
+        // calls come back, so the next instruction is reachable too
+        Opcode::Call => vec![branch_target(instruction.args[0]), next],
+        _ => return None,
+    };
+    targets.dedup();
+    Some(targets)
+}
+
+// where the instruction can jump or call to, besides the next one
+fn branch(instruction: &Instruction) -> Option<Target> {
+    match instruction.opcode {
+        Opcode::Jmp | Opcode::Call => Some(branch_target(instruction.args[0])),
+        Opcode::Jt | Opcode::Jf => Some(branch_target(instruction.args[1])),
+        _ => None,
+    }
+}
+
+fn label(address: usize, called: bool) -> String {
+    let prefix = if called { "sub" } else { "loc" };
+    format!("{}_{:04x}", prefix, address)
+}
+
+/// Disassemble the whole of `memory` in one sweep, labelling every address
+/// that's jumped or called to with where from. Words that don't decode are
+/// listed as `.word`s.
+pub fn listing(memory: &[u32]) -> String {
+    // who jumps or calls to each address, and whether any of them calls it
+    let mut xrefs = BTreeMap::<usize, (Vec<usize>, bool)>::new();
+    for (address, instruction) in disassemble(memory, 0) {
+        if let Some((Target::Address(target), opcode)) = instruction
+            .ok()
+            .and_then(|instruction| Some((branch(&instruction)?, instruction.opcode)))
+        {
+            let (from, called) = xrefs.entry(target).or_default();
+            from.push(address);
+            *called |= opcode == Opcode::Call;
+        }
+    }
+
+    // writing to a String can't fail, so the results are ignored
+    let mut listing = String::new();
+    for (address, instruction) in disassemble(memory, 0) {
+        if let Some((from, called)) = xrefs.get(&address) {
+            let from = from
+                .iter()
+                .map(|address| format!("{:#06x}", address))
+                .collect::<Vec<_>>();
+            let _ = writeln!(
+                listing,
+                "\n{}:  ; xrefs {}",
+                label(address, *called),
+                from.join(", ")
+            );
+        }
+
+        let _ = match instruction {
+            Ok(instruction) => match branch(&instruction) {
+                Some(Target::Address(target)) if xrefs.contains_key(&target) => writeln!(
+                    listing,
+                    "{:#06x}: {}  ; {}",
+                    address,
+                    instruction,
+                    label(target, xrefs[&target].1)
+                ),
+                _ => writeln!(listing, "{:#06x}: {}", address, instruction),
+            },
+            Err(_) => writeln!(listing, "{:#06x}: .word {}", address, memory[address]),
+        };
+    }
+    listing
+}
+
+/// Split the code reachable from `entry` into basic blocks, following
+/// fall-throughs, jumps and calls to literal addresses.
+pub fn cfg(memory: &[u32], entry: usize) -> Graph {
+    // first find every address a block starts at...
+    let mut leaders = BTreeSet::new();
+    let mut queue = vec![entry];
+    while let Some(leader) = queue.pop() {
+        if !leaders.insert(leader) {
+            continue;
+        }
+
+        let mut address = leader;
+        while let Ok(instruction) = decode(memory, address) {
+            if let Some(targets) = exits(address, &instruction) {
+                queue.extend(targets.into_iter().filter_map(|target| match target {
+                    Target::Address(address) => Some(address),
+                    Target::Unknown => None,
+                }));
+                break;
+            }
+            address += instruction.len();
+        }
+    }
+
+    // ...then cut the code up there
+    let blocks = leaders
+        .iter()
+        .map(|&start| {
+            let mut block = Block {
+                start,
+                instructions: Vec::new(),
+                successors: Vec::new(),
+            };
+
+            let mut address = start;
+            while let Ok(instruction) = decode(memory, address) {
+                block.instructions.push((address, instruction));
+                if let Some(targets) = exits(address, &instruction) {
+                    block.successors = targets;
+                    break;
+                }
+
+                address += instruction.len();
+                if leaders.contains(&address) {
+                    block.successors.push(Target::Address(address));
+                    break;
+                }
+            }
+
+            (start, block)
+        })
+        .collect();
+
+    Graph { entry, blocks }
+}
+
+impl Graph {
+    pub fn entry(&self) -> usize {
+        self.entry
+    }
+
+    /// The blocks, in address order.
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.values()
+    }
+
+    pub fn block(&self, start: usize) -> Option<&Block> {
+        self.blocks.get(&start)
+    }
+
+    /// Render the graph in Graphviz's DOT language, e.g. to pipe into
+    /// `dot -Tsvg`.
+    pub fn to_dot(&self) -> String {
+        // writing to a String can't fail, so the results are ignored
+        let mut dot = String::from("digraph cfg {\n    node [shape=box, fontname=monospace];\n");
+        let mut unknown = false;
+
+        for block in self.blocks() {
+            let mut label = String::new();
+            for (address, instruction) in &block.instructions {
+                let _ = write!(label, "{:#06x}: {}\\l", address, instruction);
+            }
+            let attributes = if block.start == self.entry {
+                ", peripheries=2"
+            } else {
+                ""
+            };
+            let _ = writeln!(
+                dot,
+                "    b{} [label=\"{}\"{}];",
+                block.start, label, attributes
+            );
+
+            for successor in &block.successors {
+                let _ = match successor {
+                    Target::Address(address) => {
+                        writeln!(dot, "    b{} -> b{};", block.start, address)
+                    }
+                    Target::Unknown => {
+                        unknown = true;
+                        writeln!(dot, "    b{} -> unknown;", block.start)
+                    }
+                };
+            }
+        }
+
+        if unknown {
+            dot.push_str("    unknown [label=\"?\", shape=ellipse];\n");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cfg() {
+        let memory = [
+            1, 32768, 1, // 0: set r0 1
+            7, 32768, 9, // 3: jt r0 9
+            6, 32769, // 6: jmp r1
+            21,    // 8: noop, never reached
+            19, 65, // 9: out 65
+            0,  // 11: halt
+        ];
+        let graph = cfg(&memory, 0);
+
+        let starts = graph.blocks().map(|block| block.start).collect::<Vec<_>>();
+        assert_eq!(starts, [0, 6, 9]);
+        assert_eq!(
+            graph.block(0).unwrap().successors,
+            [Target::Address(9), Target::Address(6)]
+        );
+        assert_eq!(graph.block(6).unwrap().successors, [Target::Unknown]);
+        assert!(graph.block(9).unwrap().successors.is_empty());
+        assert_eq!(graph.block(9).unwrap().instructions.len(), 2);
+
+        let dot = graph.to_dot();
+        assert!(dot.contains("b0 -> b9;"));
+        assert!(dot.contains("b6 -> unknown;"));
+        assert!(dot.contains("0x0009: out 65\\l0x000b: halt\\l"));
+    }
+
+    #[test]
+    fn test_listing() {
+        let memory = [
+            17, 5,     // 0: call 5
+            0,     // 2: halt
+            40000, // 3: data
+            21,    // 4: noop
+            7, 32768, 4,  // 5: jt r0 4
+            18, // 8: ret
+        ];
+
+        assert_eq!(
+            listing(&memory),
+            "\
+0x0000: call 5  ; sub_0005
+0x0002: halt
+0x0003: .word 40000
+
+loc_0004:  ; xrefs 0x0005
+0x0004: noop
+
+sub_0005:  ; xrefs 0x0000
+0x0005: jt r0 4  ; loc_0004
+0x0008: ret
+"
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use std::{
+    io::{self, Read},
+    sync::mpsc::{Receiver, TryRecvError},
+};
+
+/// An input that pulls bytes from a channel, so that another thread (or an
+/// async task) can feed the VM as input becomes available.
+///
+/// Reads block until at least one byte is available. Once the channel is
+/// empty and every sender is gone, reads hit end-of-file and the VM's
+/// [`InputEof`](crate::InputEof) policy takes over.
+#[derive(Debug)]
+pub struct ChannelReader {
+    receiver: Receiver<u8>,
+}
+
+impl ChannelReader {
+    pub fn new(receiver: Receiver<u8>) -> Self {
+        Self { receiver }
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        match self.receiver.recv() {
+            Ok(byte) => buf[0] = byte,
+            Err(..) => return Ok(0),
+        }
+
+        let mut len = 1;
+        while len < buf.len() {
+            match self.receiver.try_recv() {
+                Ok(byte) => {
+                    buf[len] = byte;
+                    len += 1;
+                }
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => break,
+            }
+        }
+
+        Ok(len)
+    }
+}
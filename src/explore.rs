@@ -0,0 +1,228 @@
+//! Branching exploration of the game: a [`Session`] keeps a tree of VM
+//! states, each one reached from its parent by a single command, and
+//! [`rooms`] walks the map lazily.
+
+use std::{collections::HashSet, io::Cursor};
+
+use eyre::Result;
+
+use crate::Room;
+
+type VM = crate::VM<Cursor<Vec<u8>>, Cursor<Vec<u8>>>;
+
+/// A state in a [`Session`]'s tree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+#[derive(Debug)]
+struct Node {
+    vm: Box<VM>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+
+    // the command that led here from the parent, without the newline
+    command: String,
+
+    // what the game printed after the command, up to the room if any
+    prelude: String,
+    room: Option<Room>,
+}
+
+#[derive(Debug)]
+pub struct Session {
+    nodes: Vec<Node>,
+}
+
+impl Session {
+    /// Start a session whose root is `vm`, as it is right now.
+    pub fn new(vm: Box<VM>) -> Self {
+        Self {
+            nodes: vec![Node {
+                vm,
+                parent: None,
+                children: Vec::new(),
+                command: String::new(),
+                prelude: String::new(),
+                room: None,
+            }],
+        }
+    }
+
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    /// A copy of the VM at `id` to experiment with, which the session
+    /// doesn't keep track of.
+    pub fn fork(&self, id: NodeId) -> Box<VM> {
+        self.nodes[id.0].vm.clone()
+    }
+
+    /// Run `command` on a fork of `from` until the game asks for the next
+    /// one, and record the result as a new child of `from`.
+    pub fn explore(&mut self, from: NodeId, command: &str) -> Result<NodeId> {
+        let mut vm = self.fork(from);
+        let (prelude, room) = vm.send_command(command)?;
+
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Node {
+            vm,
+            parent: Some(from),
+            children: Vec::new(),
+            command: command.to_owned(),
+            prelude,
+            room,
+        });
+        self.nodes[from.0].children.push(id);
+        Ok(id)
+    }
+
+    pub fn vm(&self, id: NodeId) -> &VM {
+        &self.nodes[id.0].vm
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id.0].parent
+    }
+
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        &self.nodes[id.0].children
+    }
+
+    /// What the game printed before describing the room at `id`.
+    pub fn prelude(&self, id: NodeId) -> &str {
+        &self.nodes[id.0].prelude
+    }
+
+    pub fn room(&self, id: NodeId) -> Option<&Room> {
+        self.nodes[id.0].room.as_ref()
+    }
+
+    /// The commands that lead from the root to `id`.
+    pub fn path(&self, mut id: NodeId) -> Vec<&str> {
+        let mut path = Vec::new();
+        while let Some(parent) = self.parent(id) {
+            path.push(self.nodes[id.0].command.as_str());
+            id = parent;
+        }
+        path.reverse();
+        path
+    }
+
+    /// The commands that lead from the last state `a` and `b` have in common
+    /// to each of them.
+    pub fn diverge(&self, a: NodeId, b: NodeId) -> (Vec<&str>, Vec<&str>) {
+        let (a_path, b_path) = (self.path(a), self.path(b));
+        let common = a_path
+            .iter()
+            .zip(&b_path)
+            .take_while(|(a, b)| a == b)
+            .count();
+        (a_path[common..].to_vec(), b_path[common..].to_vec())
+    }
+}
+
+/// Walk every room reachable from `start`, where `vm` is waiting for a
+/// command, depth-first, yielding each one as it's found along with the
+/// exits that lead there from `start`, which replayed from there get back
+/// to it. A room's exits are only tried once the iterator moves past it, so
+/// stopping early saves the rest of the walk.
+///
+/// Rooms are told apart by how they look, so ones that look exactly alike,
+/// like much of the twisty passages, are only yielded once.
+pub fn rooms(vm: Box<VM>, start: Room) -> impl Iterator<Item = Result<(Vec<String>, Room)>> {
+    let key = |room: &Room| {
+        (
+            room.title.clone(),
+            room.description.clone(),
+            room.exits.clone(),
+        )
+    };
+
+    let mut seen = HashSet::new();
+    seen.insert(key(&start));
+
+    // rooms found but not yet yielded, and the last one yielded, whose exits
+    // haven't been tried yet
+    let mut pending = vec![(vm, Vec::new(), start)];
+    let mut last: Option<(Box<VM>, Vec<String>, Room)> = None;
+
+    std::iter::from_fn(move || {
+        if let Some((vm, path, room)) = last.take() {
+            // reversed, so that the first exit is the first one walked down
+            for exit in room.exits.iter().rev() {
+                let mut vm = vm.clone();
+                let next = match vm.send_command(exit) {
+                    Ok((_, next)) => next,
+                    Err(err) => return Some(Err(err)),
+                };
+
+                // exits that kill you or lead out of the map have no room
+                if let Some(next) = next {
+                    if seen.insert(key(&next)) {
+                        let mut path = path.clone();
+                        path.push(exit.clone());
+                        pending.push((vm, path, next));
+                    }
+                }
+            }
+        }
+
+        let (vm, path, room) = pending.pop()?;
+        let found = (path.clone(), room.clone());
+        last = Some((vm, path, room));
+        Some(Ok(found))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // echo every line back, then prompt for the next one
+    fn echo_program() -> Vec<u32> {
+        let mut program = vec![
+            20, 32768, // in r0
+            19, 32768, // out r0
+            4, 32769, 32768, 10, // eq r1 r0 '\n'
+            8, 32769, 0, // jf r1 0
+        ];
+        for &ch in b"What do you do?" {
+            program.extend([19, u32::from(ch)]);
+        }
+        program.extend([6, 0]);
+        program
+    }
+
+    #[test]
+    fn test_forks_diverge() {
+        let vm = VM::from_words(
+            Cursor::new(Vec::new()),
+            Cursor::new(Vec::new()),
+            echo_program(),
+        );
+        let mut session = Session::new(vm);
+        let root = session.root();
+
+        let north = session.explore(root, "north").unwrap();
+        let south = session.explore(root, "south").unwrap();
+        let deeper = session.explore(north, "take lamp").unwrap();
+
+        assert_eq!(session.prelude(north), "north\nWhat do you do?");
+        assert_eq!(session.prelude(south), "south\nWhat do you do?");
+        assert_eq!(session.prelude(deeper), "take lamp\nWhat do you do?");
+
+        // the parent hasn't run at all
+        assert_eq!(session.vm(root).pc, 0);
+        assert!(session.vm(root).output.get_ref().is_empty());
+        assert!(session.vm(root).input.get_ref().is_empty());
+
+        assert_eq!(session.children(root), [north, south]);
+        assert_eq!(session.parent(deeper), Some(north));
+        assert_eq!(session.path(deeper), ["north", "take lamp"]);
+        assert_eq!(
+            session.diverge(deeper, south),
+            (vec!["north", "take lamp"], vec!["south"])
+        );
+    }
+}
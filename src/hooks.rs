@@ -0,0 +1,78 @@
+use std::{
+    fmt,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+/// Behavior attached to a range of memory, consulted by `rmem` and `wmem`.
+pub trait MemoryHook {
+    /// Called when `rmem` reads `value` from `address`. Returning `Some`
+    /// overrides the value that is read.
+    fn on_read(&mut self, _address: usize, _value: u32) -> Option<u32> {
+        None
+    }
+
+    /// Called when `wmem` writes `value` to `address`. The returned value is
+    /// written instead, and returning `None` intercepts the write entirely.
+    fn on_write(&mut self, _address: usize, value: u32) -> Option<u32> {
+        Some(value)
+    }
+}
+
+type SharedHook = Arc<Mutex<dyn MemoryHook + Send>>;
+
+/// The memory hooks registered on a VM, each with the range it covers.
+#[derive(Clone, Default)]
+pub struct MemoryHooks(Vec<(Range<usize>, SharedHook)>);
+
+impl MemoryHooks {
+    pub fn add(&mut self, range: Range<usize>, hook: SharedHook) {
+        self.0.push((range, hook));
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    pub(crate) fn read(&self, address: usize, mut value: u32) -> u32 {
+        for (range, hook) in &self.0 {
+            if range.contains(&address) {
+                if let Some(new_value) = hook.lock().unwrap().on_read(address, value) {
+                    value = new_value;
+                }
+            }
+        }
+
+        value
+    }
+
+    pub(crate) fn write(&self, address: usize, value: u32) -> Option<u32> {
+        self.0
+            .iter()
+            .filter(|(range, _)| range.contains(&address))
+            .try_fold(value, |value, (_, hook)| {
+                hook.lock().unwrap().on_write(address, value)
+            })
+    }
+}
+
+impl PartialEq for MemoryHooks {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len()
+            && self
+                .0
+                .iter()
+                .zip(other.0.iter())
+                .all(|((a_range, a), (b_range, b))| a_range == b_range && Arc::ptr_eq(a, b))
+    }
+}
+
+impl Eq for MemoryHooks {}
+
+impl fmt::Debug for MemoryHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|(range, _)| range))
+            .finish()
+    }
+}
@@ -0,0 +1,163 @@
+use core::fmt;
+
+use crate::{DisplayArgument, Error, INSTRUCTION_NAMES_AND_ARGS};
+
+/// The 22 opcodes of the Synacor architecture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Opcode {
+    Halt,
+    Set,
+    Push,
+    Pop,
+    Eq,
+    Gt,
+    Jmp,
+    Jt,
+    Jf,
+    Add,
+    Mult,
+    Mod,
+    And,
+    Or,
+    Not,
+    Rmem,
+    Wmem,
+    Call,
+    Ret,
+    Out,
+    In,
+    Noop,
+}
+
+impl Opcode {
+    pub const ALL: [Opcode; 22] = [
+        Opcode::Halt,
+        Opcode::Set,
+        Opcode::Push,
+        Opcode::Pop,
+        Opcode::Eq,
+        Opcode::Gt,
+        Opcode::Jmp,
+        Opcode::Jt,
+        Opcode::Jf,
+        Opcode::Add,
+        Opcode::Mult,
+        Opcode::Mod,
+        Opcode::And,
+        Opcode::Or,
+        Opcode::Not,
+        Opcode::Rmem,
+        Opcode::Wmem,
+        Opcode::Call,
+        Opcode::Ret,
+        Opcode::Out,
+        Opcode::In,
+        Opcode::Noop,
+    ];
+
+    #[inline]
+    pub fn from_u32(opcode: u32) -> Option<Self> {
+        Self::ALL.get(opcode as usize).copied()
+    }
+
+    #[inline]
+    pub fn name(self) -> &'static str {
+        INSTRUCTION_NAMES_AND_ARGS[self as usize].0
+    }
+
+    #[inline]
+    pub fn arg_count(self) -> usize {
+        INSTRUCTION_NAMES_AND_ARGS[self as usize].1
+    }
+}
+
+/// A decoded instruction. Arguments are kept raw, so they still need to go
+/// through [`VM::load`](crate::VM::load) to get at their values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub opcode: Opcode,
+
+    /// The arguments, padded with zeroes past `opcode.arg_count()`.
+    pub args: [u32; 3],
+}
+
+impl Instruction {
+    #[inline]
+    pub fn args(&self) -> &[u32] {
+        &self.args[..self.opcode.arg_count()]
+    }
+
+    /// How many words the instruction takes up in memory.
+    #[inline]
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        1 + self.opcode.arg_count()
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.opcode.name())?;
+        for &arg in self.args() {
+            if arg <= 32775 {
+                write!(f, " {}", DisplayArgument(arg))?;
+            } else {
+                write!(f, " <invalid {}>", arg)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decode instructions one after the other starting at `address`, along
+/// with where each one is. A word that doesn't decode is reported and
+/// skipped over, as it's most likely data.
+pub fn disassemble(
+    memory: &[u32],
+    mut address: usize,
+) -> impl Iterator<Item = (usize, Result<Instruction, Error>)> + '_ {
+    core::iter::from_fn(move || {
+        if address >= memory.len() {
+            return None;
+        }
+
+        let here = address;
+        let instruction = decode(memory, here);
+        address += instruction.as_ref().map_or(1, Instruction::len);
+        Some((here, instruction))
+    })
+}
+
+/// Decode the instruction at `address`.
+#[inline]
+pub fn decode(memory: &[u32], address: usize) -> Result<Instruction, Error> {
+    let opcode = *memory
+        .get(address)
+        .ok_or(Error::TruncatedInstruction(address))?;
+    let opcode = Opcode::from_u32(opcode).ok_or(Error::UnknownOpcode(opcode))?;
+    let arg_count = opcode.arg_count();
+
+    // Grab three words whatever the opcode, which is cheaper than grabbing
+    // exactly as many as needed, and blank out the ones that aren't arguments
+    let args = match memory.get(address + 1..address + 4) {
+        Some(&[a, b, c]) => [a, b, c],
+        _ => {
+            let mut args = [0; 3];
+            for (offset, arg) in args[..arg_count].iter_mut().enumerate() {
+                *arg = *memory
+                    .get(address + 1 + offset)
+                    .ok_or(Error::TruncatedInstruction(address))?;
+            }
+            args
+        }
+    };
+
+    Ok(Instruction {
+        opcode,
+        args: [
+            if arg_count > 0 { args[0] } else { 0 },
+            if arg_count > 1 { args[1] } else { 0 },
+            if arg_count > 2 { args[2] } else { 0 },
+        ],
+    })
+}
@@ -0,0 +1,51 @@
+//! Minimal stand-ins for `std::io::{Read, Write}` in `no_std` builds, to be
+//! implemented for whatever the VM's input and output are wired to.
+
+/// An input or output failure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Error;
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("I/O error")
+    }
+}
+
+impl core::error::Error for Error {}
+
+pub trait Read {
+    /// Fill `buf` completely, failing if there isn't enough input.
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Error>;
+}
+
+pub trait Write {
+    /// Write all of `buf`.
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Error>;
+
+    /// Push out anything buffered up. Outputs without a buffer needn't
+    /// bother.
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl Read for alloc::collections::VecDeque<u8> {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        let len = buf.len();
+        if self.len() < len {
+            return Err(Error);
+        }
+
+        for (dst, src) in buf.iter_mut().zip(self.drain(..len)) {
+            *dst = src;
+        }
+        Ok(())
+    }
+}
+
+impl Write for alloc::vec::Vec<u8> {
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Error> {
+        self.extend_from_slice(buf);
+        Ok(())
+    }
+}
@@ -1,5 +1,65 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
 mod vm;
 pub use vm::*;
 
+mod register;
+pub use register::Register;
+
+mod instruction;
+pub use instruction::{decode, disassemble, Instruction, Opcode};
+
+mod cfg;
+pub use cfg::{cfg, listing, Block, Graph, Target};
+
+mod prompt;
+pub use prompt::{NeedleDetector, PromptDetector};
+
+#[cfg(not(feature = "std"))]
+pub mod io;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+#[cfg(feature = "std")]
+mod snapshot;
+#[cfg(feature = "std")]
+pub use snapshot::{run_file, run_program_file, run_snapshot_file};
+
+#[cfg(feature = "std")]
 mod room;
+#[cfg(feature = "std")]
 pub use room::Room;
+
+#[cfg(feature = "std")]
+mod hooks;
+#[cfg(feature = "std")]
+pub use hooks::{MemoryHook, MemoryHooks};
+
+#[cfg(feature = "std")]
+mod builder;
+#[cfg(feature = "std")]
+pub use builder::VmBuilder;
+
+#[cfg(feature = "std")]
+pub mod explore;
+
+#[cfg(feature = "std")]
+mod channel;
+#[cfg(feature = "std")]
+pub use channel::ChannelReader;
+
+#[cfg(feature = "std")]
+mod tee;
+#[cfg(feature = "std")]
+pub use tee::Tee;
+
+#[cfg(feature = "std")]
+mod trace;
+#[cfg(feature = "std")]
+pub use trace::Trace;
+
+#[cfg(feature = "solvers")]
+pub mod solvers;
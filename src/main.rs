@@ -1,28 +1,99 @@
 use std::{
     convert::TryFrom,
-    env, fs,
-    io::{self, Cursor, Seek, Write},
+    fs,
+    io::{self, Cursor, Read, Seek, Write},
+    path::PathBuf,
 };
 
-use crossterm::{
-    event::{Event, KeyCode},
-    write_ansi_code,
-};
-use eyre::{bail, Result};
+use clap::{Parser, ValueEnum};
+use crossterm::event::{Event, KeyCode, KeyModifiers};
+use eyre::{bail, eyre, Result};
 
 use tui::{
     layout::*,
     style::*,
-    text::{Span, Spans},
+    text::{Span, Spans, Text},
     widgets::*,
 };
 
 type VM = synacor_vm::VM<Cursor<Vec<u8>>, Cursor<Vec<u8>>>;
 
+/// Where Ctrl+S and Ctrl+L save and load.
+const QUICKSAVE: &str = "quicksave.bin";
+
+/// Interactive debugger for the Synacor Challenge VM.
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// Resume from a snapshot instead of starting a program from scratch, `-` for stdin
+    #[arg(long, conflicts_with = "program")]
+    snapshot: Option<PathBuf>,
+
+    /// Run this program instead of the embedded challenge.bin, `-` for stdin
+    #[arg(long)]
+    program: Option<PathBuf>,
+
+    /// Where to save a snapshot on exit [default: snapshot.bin, or nowhere with --no-tui]
+    #[arg(long)]
+    snapshot_out: Option<PathBuf>,
+
+    /// Run headless over stdin and stdout instead of starting the TUI
+    #[arg(long)]
+    no_tui: bool,
+
+    /// Append every submitted input line to this file
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Feed this file to the VM as input before handing control over
+    #[arg(long)]
+    replay: Option<PathBuf>,
+
+    /// Colors to suit the terminal's background
+    #[arg(long, value_enum, default_value_t = ThemePreset::Dark)]
+    theme: ThemePreset,
+}
+
+impl Args {
+    /// Load the VM described by the arguments. The returned flag tells
+    /// whether the VM is starting a program from scratch.
+    fn load_vm<Input: Read, Output: Write>(
+        &self,
+        input: Input,
+        output: Output,
+    ) -> Result<(Box<synacor_vm::VM<Input, Output>>, bool)> {
+        Ok(if let Some(snapshot) = &self.snapshot {
+            let vm = synacor_vm::run_snapshot_file(snapshot, input, output)?;
+            (vm, false)
+        } else if let Some(program) = &self.program {
+            let vm = synacor_vm::run_program_file(program, input, output)?;
+            (vm, true)
+        } else {
+            let vm = synacor_vm::VM::load_program(input, output, include_bytes!("challenge.bin"));
+            (vm, true)
+        })
+    }
+}
+
+fn run_headless(args: &Args) -> Result<()> {
+    // not locked, as the program or snapshot may be coming in on stdin
+    let (mut vm, _) = args.load_vm(io::stdin(), io::stdout().lock())?;
+    vm.enable_output_buffering();
+
+    vm.run_to_halt()?;
+
+    if let Some(snapshot_out) = &args.snapshot_out {
+        vm.save_snapshot(fs::File::create(snapshot_out)?)?;
+    }
+
+    Ok(())
+}
+
 fn run_until_prompt(vm: &mut VM, writes: &mut Vec<(u32, u32)>) -> Result<()> {
     let pos = usize::try_from(vm.output.position())?;
+    let mut prompt = synacor_vm::PromptDetector::new(pos);
 
-    while !vm.output.get_ref()[pos..].ends_with(b"What do you do?") {
+    while !prompt.update(vm.output.get_ref()) {
         if vm.memory[vm.pc] == 16 {
             let dest = vm.load(vm.memory[vm.pc + 1])?;
             let src = vm.load(vm.memory[vm.pc + 2])?;
@@ -32,7 +103,10 @@ fn run_until_prompt(vm: &mut VM, writes: &mut Vec<(u32, u32)>) -> Result<()> {
         match vm.cycle() {
             Ok(()) => {}
             Err(err) => {
-                if let Some(synacor_vm::Error::Halt) = err.downcast_ref::<synacor_vm::Error>() {
+                if err
+                    .downcast_ref::<synacor_vm::Error>()
+                    .is_some_and(synacor_vm::Error::is_halt)
+                {
                     break;
                 }
 
@@ -50,15 +124,157 @@ fn run_until_prompt(vm: &mut VM, writes: &mut Vec<(u32, u32)>) -> Result<()> {
     Ok(())
 }
 
-fn make_output_widget(vm: &VM) -> Paragraph {
-    Paragraph::new(
-        std::str::from_utf8(&vm.output.get_ref()[vm.output.position() as usize..]).unwrap(),
-    )
-    .block(Block::default().borders(Borders::ALL).title("Output"))
-    .wrap(Wrap { trim: true })
+/// How the output panel lays out long lines.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OutputMode {
+    /// Reflow lines to fit, which suits prose.
+    Wrapped,
+
+    /// Leave lines as the game printed them, scrolled this many columns to
+    /// the right, which suits ASCII art and maps.
+    Raw { scroll: u16 },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ThemePreset {
+    Dark,
+    Light,
+}
+
+/// How the widgets are styled.
+#[derive(Clone, Copy)]
+struct Theme {
+    border: Style,
+    label: Style,
+    current_instruction: Style,
+    changed_register: Style,
+    new_output: Style,
+    status: Style,
+}
+
+impl Theme {
+    fn dark() -> Self {
+        Self {
+            border: Style::default(),
+            label: Style::default().add_modifier(Modifier::BOLD),
+            current_instruction: Style::default().fg(Color::Cyan),
+            changed_register: Style::default().fg(Color::Yellow),
+            new_output: Style::default(),
+            status: Style::default().add_modifier(Modifier::DIM),
+        }
+    }
+
+    fn light() -> Self {
+        // yellow and cyan all but vanish on a white background
+        Self {
+            current_instruction: Style::default().fg(Color::Blue),
+            changed_register: Style::default().fg(Color::Magenta),
+            ..Self::dark()
+        }
+    }
+
+    /// A bordered block with the given title.
+    fn block<'a>(&self, title: &'a str) -> Block<'a> {
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(self.border)
+            .title(Span::styled(title, self.label))
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+impl From<ThemePreset> for Theme {
+    fn from(preset: ThemePreset) -> Self {
+        match preset {
+            ThemePreset::Dark => Self::dark(),
+            ThemePreset::Light => Self::light(),
+        }
+    }
+}
+
+/// Where each widget goes on the screen.
+struct Panes {
+    output: Rect,
+    instruction: Rect,
+    state: Rect,
+    writes: Rect,
+    input: Rect,
+    status: Rect,
+}
+
+impl Panes {
+    fn new(size: Rect) -> Self {
+        let output_n_input = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(
+                [
+                    Constraint::Percentage(90),
+                    Constraint::Length(1),
+                    Constraint::Length(1),
+                ]
+                .as_ref(),
+            )
+            .split(size);
+
+        let output_n_debug = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(85), Constraint::Percentage(25)])
+            .split(output_n_input[0]);
+
+        let state_n_writes = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(
+                [
+                    Constraint::Length(3),
+                    Constraint::Min(11),
+                    Constraint::Percentage(100),
+                ]
+                .as_ref(),
+            )
+            .split(output_n_debug[1]);
+
+        Self {
+            output: output_n_debug[0],
+            instruction: state_n_writes[0],
+            state: state_n_writes[1],
+            writes: state_n_writes[2],
+            input: output_n_input[1],
+            status: output_n_input[2],
+        }
+    }
+}
+
+fn make_output_widget<'a>(vm: &'a VM, mode: OutputMode, theme: &Theme) -> Paragraph<'a> {
+    // `out` can write any byte at all, so stray ones show up as U+FFFD
+    // rather than taking the whole TUI down
+    let output = Paragraph::new(Text::raw(String::from_utf8_lossy(
+        &vm.output.get_ref()[vm.output.position() as usize..],
+    )))
+    .style(theme.new_output);
+
+    match mode {
+        OutputMode::Wrapped => output
+            .block(theme.block("Output"))
+            .wrap(Wrap { trim: true }),
+        OutputMode::Raw { scroll } => output
+            .block(theme.block("Output (raw)"))
+            .scroll((0, scroll)),
+    }
+}
+
+/// The furthest `panel` can be scrolled down the writes, which leaves the
+/// last write at the bottom of a full panel, or 0 if they all fit.
+fn last_page(writes: &Cursor<Vec<(u32, u32)>>, panel: Rect) -> u64 {
+    let visible = u64::from(panel.height.saturating_sub(2));
+    (writes.get_ref().len() as u64).saturating_sub(visible)
 }
 
-fn make_writes_widget(writes: &Cursor<Vec<(u32, u32)>>) -> List {
+fn make_writes_widget<'a>(writes: &'a Cursor<Vec<(u32, u32)>>, theme: &Theme) -> List<'a> {
     List::new(
         writes
             .get_ref()
@@ -73,56 +289,203 @@ fn make_writes_widget(writes: &Cursor<Vec<(u32, u32)>>) -> List {
             })
             .collect::<Vec<_>>(),
     )
-    .block(Block::default().borders(Borders::ALL).title("Writes"))
+    .block(theme.block("Writes"))
+}
+
+/// The pc and registers as they were last drawn.
+#[derive(Clone, Copy)]
+struct Drawn {
+    pc: usize,
+    registers: [u32; 8],
+}
+
+impl Drawn {
+    fn new(vm: &VM) -> Self {
+        Self {
+            pc: vm.pc,
+            registers: vm.registers,
+        }
+    }
 }
 
-fn make_state_widget(vm: &VM) -> Table {
+/// Show the pc and registers, highlighting the ones that changed since they
+/// were last drawn.
+fn make_state_widget(vm: &VM, last_drawn: Option<Drawn>, theme: &Theme) -> Table<'static> {
+    let value = |value: String, changed: bool| {
+        let style = if changed {
+            theme.changed_register
+        } else {
+            Style::default()
+        };
+        Cell::from(value).style(style)
+    };
+
     let mut rows = vec![Row::new(vec![
-        Cell::from("pc").style(Style::default().add_modifier(Modifier::BOLD)),
-        Cell::from(vm.pc.to_string()),
+        Cell::from("pc").style(theme.label),
+        value(
+            vm.pc.to_string(),
+            last_drawn.is_some_and(|drawn| drawn.pc != vm.pc),
+        ),
     ])];
 
-    for (idx, register) in vm.registers.iter().enumerate() {
+    for (idx, &register) in vm.registers.iter().enumerate() {
         rows.push(Row::new(vec![
-            Cell::from(format!("r{}", idx)).style(Style::default().add_modifier(Modifier::BOLD)),
-            Cell::from(register.to_string()),
+            Cell::from(format!("r{}", idx)).style(theme.label),
+            value(
+                register.to_string(),
+                last_drawn.is_some_and(|drawn| drawn.registers[idx] != register),
+            ),
         ]))
     }
 
     Table::new(rows)
-        .block(Block::default().borders(Borders::ALL).title("State"))
+        .block(theme.block("State"))
         .widths(&[Constraint::Percentage(50), Constraint::Percentage(50)])
 }
 
-fn make_prompt_widget(vm: &VM) -> Paragraph {
-    Paragraph::new(
-        std::str::from_utf8(&vm.input.get_ref()[vm.input.position() as usize..]).unwrap(),
-    )
-    .block(Block::default().borders(Borders::ALL).title("Input"))
+/// The instruction about to run, with the current values of the registers it
+/// mentions, like `5483: add r0=4 r1=2 1`.
+fn make_current_instruction_widget(vm: &VM, theme: &Theme) -> Paragraph<'static> {
+    let text = match synacor_vm::decode(&vm.memory, vm.pc) {
+        Ok(instruction) => {
+            let mut text = format!("{}: {}", vm.pc, instruction.opcode.name());
+            for &arg in instruction.args() {
+                text += &match synacor_vm::Register::try_from(arg) {
+                    Ok(register) => format!(" {}={}", register, vm.register(register)),
+                    Err(arg) => format!(" {}", arg),
+                };
+            }
+            text
+        }
+        Err(_) => match vm.memory.get(vm.pc) {
+            Some(word) => format!("{}: .word {}", vm.pc, word),
+            None => format!("{}: past the end of memory", vm.pc),
+        },
+    };
+
+    Paragraph::new(text)
+        .style(theme.current_instruction)
+        .block(theme.block("Next"))
+}
+
+fn make_prompt_widget<'a>(vm: &'a VM, theme: &Theme) -> Paragraph<'a> {
+    Paragraph::new(Text::raw(String::from_utf8_lossy(
+        &vm.input.get_ref()[vm.input.position() as usize..],
+    )))
+    .block(theme.block("Input"))
     .wrap(Wrap { trim: true })
 }
 
+fn make_status_widget<'a>(status: &'a str, theme: &Theme) -> Paragraph<'a> {
+    Paragraph::new(status).style(theme.status)
+}
+
+/// Copy `text` to the system clipboard, opening it first if need be, and
+/// say how that went. Failing isn't fatal: there may well be no clipboard,
+/// e.g. over SSH.
+fn copy_to_clipboard(clipboard: &mut Option<arboard::Clipboard>, text: &str) -> String {
+    // on some platforms the copied text only lasts as long as the clipboard
+    // handle does, so it's kept around
+    let copied = match clipboard {
+        Some(clipboard) => clipboard.set_text(text),
+        None => arboard::Clipboard::new()
+            .and_then(|opened| clipboard.get_or_insert(opened).set_text(text)),
+    };
+
+    match copied {
+        Ok(()) => format!("copied {} bytes", text.len()),
+        Err(err) => format!("couldn't copy: {}", err),
+    }
+}
+
+fn quick_save(vm: &VM) -> Result<String> {
+    vm.save_snapshot(fs::File::create(QUICKSAVE)?)?;
+    Ok(format!("saved {}", QUICKSAVE))
+}
+
+fn quick_load(vm: &mut VM) -> Result<String> {
+    let snapshot = fs::read(QUICKSAVE)?;
+    vm.load_snapshot_inplace(&snapshot[..])?;
+    Ok(format!("loaded {}", QUICKSAVE))
+}
+
+/// Leave the alternate screen and raw mode, as best we can.
+fn restore_terminal() {
+    let _ = crossterm::execute!(io::stdout(), crossterm::terminal::LeaveAlternateScreen);
+    let _ = crossterm::terminal::disable_raw_mode();
+}
+
+/// Run a `:` command typed at the prompt, returning what to say about it.
+fn run_command(vm: &mut VM, writes: &mut Vec<(u32, u32)>, line: &str) -> Result<String> {
+    let mut words = line.split_whitespace();
+    match (words.next(), words.next()) {
+        (Some("reload"), Some(path)) => {
+            vm.reload_program(&fs::read(path)?)?;
+            vm.output.seek(io::SeekFrom::End(0))?;
+            run_until_prompt(vm, writes)?;
+            Ok(format!("reloaded {}", path))
+        }
+        (Some("reload"), None) => bail!(eyre!("usage: :reload <path>")),
+        (Some("disas-dump"), Some(path)) => {
+            // memory as it is now, self-modifications and all
+            fs::write(path, synacor_vm::listing(&vm.memory))?;
+            Ok(format!("wrote the disassembly to {}", path))
+        }
+        (Some("disas-dump"), None) => bail!(eyre!("usage: :disas-dump <path>")),
+        _ => bail!(eyre!("unknown command {:?}", line.trim())),
+    }
+}
+
 fn main() -> Result<()> {
     color_eyre::install()?;
 
+    let args = Args::parse();
+    if args.no_tui {
+        return run_headless(&args);
+    }
+
     let mut writes = Cursor::new(Vec::new());
-    let mut vm;
-
-    if let Some(snapshot) = env::args().nth(1) {
-        vm = VM::load_snapshot(
-            Cursor::new(Vec::new()),
-            Cursor::new(Vec::new()),
-            fs::File::open(snapshot)?,
-        )?;
-    } else {
-        vm = VM::load_program(
-            Cursor::new(Vec::new()),
-            Cursor::new(Vec::new()),
-            include_bytes!("challenge.bin"),
-        );
+
+    let (mut vm, fresh) = args.load_vm(Cursor::new(Vec::new()), Cursor::new(Vec::new()))?;
+    if fresh {
         run_until_prompt(&mut vm, writes.get_mut())?;
     }
 
+    if let Some(replay) = &args.replay {
+        vm.output.seek(io::SeekFrom::End(0))?;
+        vm.append_input(fs::read(replay)?)?;
+
+        // Run prompt by prompt until the whole script has been read, stopping
+        // early if the VM halts without reading anything
+        loop {
+            let before = vm.input.position();
+            if before == vm.input.get_ref().len() as u64 {
+                break;
+            }
+
+            writes.get_mut().clear();
+            writes.set_position(0);
+            run_until_prompt(&mut vm, writes.get_mut())?;
+
+            if vm.input.position() == before {
+                break;
+            }
+        }
+
+        vm.input.seek(io::SeekFrom::End(0))?;
+    }
+
+    let mut record = args
+        .record
+        .as_ref()
+        .map(|record| {
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(record)
+        })
+        .transpose()?;
+
     // Initialize our tui::Terminal
     let mut terminal = {
         let stdout = io::stdout();
@@ -135,34 +498,46 @@ fn main() -> Result<()> {
     crossterm::terminal::enable_raw_mode()?;
     crossterm::execute!(io::stdout(), crossterm::terminal::EnterAlternateScreen)?;
     scopeguard::defer! {
-        let _ = crossterm::execute!(io::stdout(), crossterm::terminal::LeaveAlternateScreen);
-        let _ = crossterm::terminal::disable_raw_mode();
+        restore_terminal();
     };
 
+    // a panic's message would be lost in the alternate screen and garbled by
+    // raw mode, and the defer above only runs once it's been printed, if at all
+    let panic_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore_terminal();
+        panic_hook(info);
+    }));
+
+    let theme = Theme::from(args.theme);
+    let mut last_drawn = None;
+    let mut output_mode = OutputMode::Wrapped;
+    let mut clipboard = None;
+    let mut status = String::new();
+    let mut writes_panel = Rect::default();
+
     loop {
         terminal.draw(|frame| {
-            let output_n_input = Layout::default()
-                .direction(Direction::Vertical)
-                .constraints([Constraint::Percentage(90), Constraint::Length(1)].as_ref())
-                .split(frame.size());
-
-            let output_n_debug = Layout::default()
-                .direction(Direction::Horizontal)
-                .constraints([Constraint::Percentage(85), Constraint::Percentage(25)])
-                .split(output_n_input[0]);
-
-            let state_n_writes = Layout::default()
-                .direction(Direction::Vertical)
-                .constraints([Constraint::Min(11), Constraint::Percentage(100)])
-                .split(output_n_debug[1]);
-
-            frame.render_widget(make_output_widget(&vm), output_n_debug[0]);
-            frame.render_widget(make_state_widget(&vm), state_n_writes[0]);
-            frame.render_widget(make_writes_widget(&writes), state_n_writes[1]);
-            frame.render_widget(make_prompt_widget(&vm), output_n_input[1]);
+            let panes = Panes::new(frame.size());
+            writes_panel = panes.writes;
+            frame.render_widget(make_output_widget(&vm, output_mode, &theme), panes.output);
+            frame.render_widget(
+                make_current_instruction_widget(&vm, &theme),
+                panes.instruction,
+            );
+            frame.render_widget(make_state_widget(&vm, last_drawn, &theme), panes.state);
+            frame.render_widget(make_writes_widget(&writes, &theme), panes.writes);
+            frame.render_widget(make_prompt_widget(&vm, &theme), panes.input);
+            frame.render_widget(make_status_widget(&status, &theme), panes.status);
         })?;
+        last_drawn = Some(Drawn::new(&vm));
+
+        let event = crossterm::event::read()?;
 
-        match crossterm::event::read()? {
+        // the status only describes whatever the last key did
+        status.clear();
+
+        match event {
             Event::Key(evt) => match evt.code {
                 KeyCode::Backspace => {
                     if !matches!(
@@ -173,7 +548,29 @@ fn main() -> Result<()> {
                     }
                 }
 
+                // lines starting with ':' are commands for the debugger itself
+                KeyCode::Enter
+                    if vm.input.get_ref().get(vm.input.position() as usize) == Some(&b':') =>
+                {
+                    // take the command back out of the input so the program never sees it
+                    let position = vm.input.position() as usize;
+                    let line = vm.input.get_mut().split_off(position);
+                    let line = String::from_utf8_lossy(&line[1..]);
+
+                    writes.get_mut().clear();
+                    writes.set_position(0);
+                    status = match run_command(&mut vm, writes.get_mut(), &line) {
+                        Ok(status) => status,
+                        Err(err) => err.to_string(),
+                    };
+                }
+
                 KeyCode::Enter => {
+                    if let Some(record) = &mut record {
+                        record.write_all(&vm.input.get_ref()[vm.input.position() as usize..])?;
+                        record.write_all(b"\n")?;
+                    }
+
                     vm.output.seek(io::SeekFrom::End(0))?;
                     vm.append_input(b"\n")?;
                     writes.get_mut().clear();
@@ -182,20 +579,67 @@ fn main() -> Result<()> {
                     vm.input.seek(io::SeekFrom::End(0))?;
                 }
 
-                KeyCode::Char(ch) => vm.append_input(&[ch as u8])?,
+                KeyCode::Char('s') if evt.modifiers.contains(KeyModifiers::CONTROL) => {
+                    status = quick_save(&vm).unwrap_or_else(|err| err.to_string());
+                }
+
+                KeyCode::Char('l') if evt.modifiers.contains(KeyModifiers::CONTROL) => {
+                    status = match quick_load(&mut vm) {
+                        Ok(status) => {
+                            // none of what's on screen describes the loaded state anymore
+                            vm.output.seek(io::SeekFrom::End(0))?;
+                            writes.get_mut().clear();
+                            writes.set_position(0);
+                            last_drawn = None;
+                            status
+                        }
+                        Err(err) => err.to_string(),
+                    };
+                }
+
+                KeyCode::Char(ch) => vm.append_input([ch as u8])?,
 
                 KeyCode::Esc => break,
 
+                KeyCode::F(2) => {
+                    output_mode = match output_mode {
+                        OutputMode::Wrapped => OutputMode::Raw { scroll: 0 },
+                        OutputMode::Raw { .. } => OutputMode::Wrapped,
+                    };
+                }
+
+                KeyCode::F(3) => {
+                    let onscreen = &vm.output.get_ref()[vm.output.position() as usize..];
+                    status = copy_to_clipboard(&mut clipboard, &String::from_utf8_lossy(onscreen));
+                }
+
+                KeyCode::Left => {
+                    if let OutputMode::Raw { scroll } = &mut output_mode {
+                        *scroll = scroll.saturating_sub(1);
+                    }
+                }
+
+                KeyCode::Right => {
+                    if let OutputMode::Raw { scroll } = &mut output_mode {
+                        *scroll = scroll.saturating_add(1);
+                    }
+                }
+
                 KeyCode::PageUp => {
                     let new_pos = writes.position().saturating_sub(1);
                     writes.set_position(new_pos);
                 }
 
                 KeyCode::PageDown => {
-                    let new_pos = writes.position() + 1;
+                    // stop once the last write is in view rather than scrolling off into nothing
+                    let new_pos = (writes.position() + 1).min(last_page(&writes, writes_panel));
                     writes.set_position(new_pos);
                 }
 
+                KeyCode::Home => writes.set_position(0),
+
+                KeyCode::End => writes.set_position(last_page(&writes, writes_panel)),
+
                 KeyCode::Up => {
                     // Get the output offscreen
                     let offscreen = &vm.output.get_ref()[..vm.output.position() as usize];
@@ -261,10 +705,6 @@ fn main() -> Result<()> {
 
                 KeyCode::F(..)
                 | KeyCode::Null
-                | KeyCode::Left
-                | KeyCode::Right
-                | KeyCode::Home
-                | KeyCode::End
                 | KeyCode::Tab
                 | KeyCode::BackTab
                 | KeyCode::Delete
@@ -273,11 +713,57 @@ fn main() -> Result<()> {
 
             Event::Mouse(..) => unreachable!(),
 
-            Event::Resize(..) => {}
+            Event::Resize(columns, rows) => {
+                // the writes panel may have shrunk or grown, so make sure
+                // it's still scrolled no further than it takes to fill it
+                writes_panel = Panes::new(Rect::new(0, 0, columns, rows)).writes;
+                writes.set_position(writes.position().min(last_page(&writes, writes_panel)));
+            }
         }
     }
 
-    vm.save_snapshot(fs::File::create("snapshot.bin")?)?;
+    let snapshot_out = args
+        .snapshot_out
+        .unwrap_or_else(|| PathBuf::from("snapshot.bin"));
+    vm.save_snapshot(fs::File::create(snapshot_out)?)?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_invalid_utf8() {
+        // out 65, out 128, out 10, halt
+        let program = &[19, 0, 65, 0, 19, 0, 128, 0, 19, 0, 10, 0, 0, 0];
+        let mut vm =
+            synacor_vm::VM::load_program(Cursor::new(vec![0x80]), Cursor::new(Vec::new()), program);
+        vm.run_to_halt().unwrap();
+        assert_eq!(vm.output.get_ref(), &[65, 0x80, 10]);
+        vm.output.set_position(0);
+
+        let area = Rect::new(0, 0, 10, 3);
+        let theme = Theme::default();
+        for mode in [OutputMode::Wrapped, OutputMode::Raw { scroll: 0 }] {
+            let mut buffer = tui::buffer::Buffer::empty(area);
+            make_output_widget(&vm, mode, &theme).render(area, &mut buffer);
+            assert_eq!(buffer.get(1, 1).symbol, "A");
+            assert_eq!(buffer.get(2, 1).symbol, "\u{fffd}");
+        }
+
+        let mut buffer = tui::buffer::Buffer::empty(area);
+        make_prompt_widget(&vm, &theme).render(area, &mut buffer);
+        assert_eq!(buffer.get(1, 1).symbol, "\u{fffd}");
+    }
+
+    #[test]
+    fn test_last_page() {
+        // 5 rows inside the borders
+        let panel = Rect::new(0, 0, 20, 7);
+        assert_eq!(last_page(&Cursor::new(vec![(0, 0); 12]), panel), 7);
+        assert_eq!(last_page(&Cursor::new(vec![(0, 0); 3]), panel), 0);
+        assert_eq!(last_page(&Cursor::new(Vec::new()), Rect::default()), 0);
+    }
+}
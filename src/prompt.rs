@@ -0,0 +1,141 @@
+use alloc::{vec, vec::Vec};
+
+/// Spots the game's prompt at the end of the output as it's produced,
+/// looking at every byte only once, where checking the whole output with
+/// `ends_with` after every instruction would keep going over it again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PromptDetector {
+    // how much of the output has been looked at already
+    seen: usize,
+
+    // how many bytes of the prompt the output currently ends with
+    matched: usize,
+}
+
+impl PromptDetector {
+    pub const PROMPT: &'static [u8] = b"What do you do?";
+
+    /// Start watching the output from `start` onward.
+    pub fn new(start: usize) -> Self {
+        Self {
+            seen: start,
+            matched: 0,
+        }
+    }
+
+    /// Look at whatever's been added to `output` since the last call, and
+    /// say whether it now ends with the prompt.
+    pub fn update(&mut self, output: &[u8]) -> bool {
+        for &byte in output.get(self.seen..).unwrap_or_default() {
+            // no proper prefix of the prompt is also a suffix of it, so a
+            // mismatch can only restart the match, never resume it partway
+            self.matched = if Self::PROMPT.get(self.matched) == Some(&byte) {
+                self.matched + 1
+            } else {
+                usize::from(byte == Self::PROMPT[0])
+            };
+        }
+        self.seen = self.seen.max(output.len());
+        self.matched == Self::PROMPT.len()
+    }
+}
+
+/// Like [`PromptDetector`], but for any needle at all.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NeedleDetector<'a> {
+    needle: &'a [u8],
+
+    // for each prefix of the needle, the length of its longest proper prefix
+    // that's also a suffix of it: where to resume after a mismatch
+    fallback: Vec<usize>,
+
+    seen: usize,
+    matched: usize,
+}
+
+impl<'a> NeedleDetector<'a> {
+    /// Start watching the output from `start` onward.
+    pub fn new(needle: &'a [u8], start: usize) -> Self {
+        let mut fallback = vec![0; needle.len()];
+        let mut len = 0;
+        for i in 1..needle.len() {
+            while len > 0 && needle[i] != needle[len] {
+                len = fallback[len - 1];
+            }
+            if needle[i] == needle[len] {
+                len += 1;
+            }
+            fallback[i] = len;
+        }
+
+        Self {
+            needle,
+            fallback,
+            seen: start,
+            matched: 0,
+        }
+    }
+
+    /// Look at whatever's been added to `output` since the last call, and
+    /// say whether the needle has shown up yet. Output can come in big
+    /// chunks, so once it has, the answer stays yes however much follows.
+    pub fn update(&mut self, output: &[u8]) -> bool {
+        for &byte in output.get(self.seen..).unwrap_or_default() {
+            if self.matched == self.needle.len() {
+                break;
+            }
+
+            while self.matched > 0 && self.needle[self.matched] != byte {
+                self.matched = self.fallback[self.matched - 1];
+            }
+            if self.needle.get(self.matched) == Some(&byte) {
+                self.matched += 1;
+            }
+        }
+        self.seen = self.seen.max(output.len());
+        self.matched == self.needle.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prompt_detector() {
+        let output = b"Hi.\nWhat do\nWhat do you do? ok\nWhat do you do?";
+        let mut detector = PromptDetector::new(0);
+        let mut found = alloc::vec::Vec::new();
+        for end in 0..=output.len() {
+            if detector.update(&output[..end]) {
+                found.push(end);
+            }
+        }
+        assert_eq!(found, [27, output.len()]);
+
+        // bigger chunks, starting partway in
+        let mut detector = PromptDetector::new(4);
+        assert!(!detector.update(&output[..20]));
+        assert!(detector.update(&output[..27]));
+        assert!(!detector.update(&output[..30]));
+        assert!(detector.update(output));
+    }
+
+    #[test]
+    fn test_needle_detector() {
+        // a mismatch partway through has to fall back to "aab", not restart
+        let output = b"aaabaaab";
+        let mut detector = NeedleDetector::new(b"aabaaab", 0);
+        let found = (0..=output.len())
+            .filter(|&end| detector.update(&output[..end]))
+            .collect::<Vec<_>>();
+        assert_eq!(found, [8]);
+
+        // all in one go, with more after it
+        let mut detector = NeedleDetector::new(b"aab", 0);
+        assert!(detector.update(b"xaabyyy"));
+        assert!(detector.update(b"xaabyyyzzz"));
+
+        assert!(NeedleDetector::new(b"", 0).update(b""));
+    }
+}
@@ -0,0 +1,68 @@
+use core::{convert::TryFrom, fmt};
+
+use crate::REGISTER_COUNT;
+
+// how instruction arguments refer to the first register
+const FIRST_REGISTER: u32 = 32768;
+
+/// One of the VM's eight registers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Register(u8);
+
+impl Register {
+    pub const R0: Register = Register(0);
+    pub const R1: Register = Register(1);
+    pub const R2: Register = Register(2);
+    pub const R3: Register = Register(3);
+    pub const R4: Register = Register(4);
+    pub const R5: Register = Register(5);
+    pub const R6: Register = Register(6);
+    pub const R7: Register = Register(7);
+
+    pub const ALL: [Register; REGISTER_COUNT] = [
+        Register::R0,
+        Register::R1,
+        Register::R2,
+        Register::R3,
+        Register::R4,
+        Register::R5,
+        Register::R6,
+        Register::R7,
+    ];
+
+    /// The register numbered `index`, if there is one.
+    pub fn new(index: u8) -> Option<Self> {
+        Self::ALL.get(usize::from(index)).copied()
+    }
+
+    /// Where this register is in [`VM::registers`](crate::VM::registers).
+    #[inline]
+    pub fn index(self) -> usize {
+        usize::from(self.0)
+    }
+
+    /// How an instruction argument refers to this register.
+    pub fn encode(self) -> u32 {
+        FIRST_REGISTER + u32::from(self.0)
+    }
+}
+
+/// Decode an instruction argument that refers to a register, handing back
+/// any other value as the error.
+impl TryFrom<u32> for Register {
+    type Error = u32;
+
+    #[inline]
+    fn try_from(value: u32) -> Result<Self, u32> {
+        match value.checked_sub(FIRST_REGISTER) {
+            Some(index) if index < REGISTER_COUNT as u32 => Ok(Register(index as u8)),
+            _ => Err(value),
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "r{}", self.0)
+    }
+}
@@ -16,12 +16,37 @@ There are 2 exits:
 What do you do?
 */
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Room {
     pub title: String,
     pub description: String,
     pub items: Vec<String>,
     pub exits: Vec<String>,
+
+    /// Whether this is one of the twisty passages, whose descriptions are
+    /// the same all over so only the exits tell them apart.
+    pub maze: bool,
+}
+
+// the maze descriptions shuffle their words around ("a twisty maze of
+// little passages", "a twisty alike of little passages, all maze", ...) but
+// these always show up
+fn is_maze(description: &str) -> bool {
+    description.contains("twisty") && description.contains("passages")
+}
+
+const PROMPT: &str = "What do you do?";
+
+// only the lists' own headers end a description, since a line of it could
+// well end in a colon too
+fn is_header(line: &str) -> bool {
+    let line = line.trim_end();
+    line == "Things of interest here:"
+        || (line.starts_with("There ") && (line.ends_with(" exit:") || line.ends_with(" exits:")))
+}
+
+fn is_prompt(line: &str) -> bool {
+    line.trim_end() == PROMPT
 }
 
 impl Room {
@@ -31,6 +56,7 @@ impl Room {
             description: String::new(),
             items: Vec::new(),
             exits: Vec::new(),
+            maze: false,
         };
 
         // read everything until the room start header and treat it as the
@@ -54,26 +80,29 @@ impl Room {
         this.title.drain(..2);
         this.title.drain(this.title.len() - 4..);
 
-        // read room description until empty line
+        // read room description until the first list or the prompt, blank
+        // lines between its paragraphs and all
         let mut header = String::new();
         loop {
             header.clear();
-            b.read_line(&mut header)?;
+            if b.read_line(&mut header)? == 0 {
+                bail!(eyre!("room {:?} ends before {:?}", this.title, PROMPT));
+            }
 
-            if header == "What do you do?" || header.ends_with(":\n") {
+            if is_prompt(&header) || is_header(&header) {
                 break;
             }
 
             this.description.push_str(&header);
         }
 
-        // remove junk from description
-        if this.description.ends_with("\n\n") {
-            this.description.drain(this.description.len() - 2..);
-        }
+        // remove the blank lines separating the description from what's next
+        let len = this.description.trim_end_matches('\n').len();
+        this.description.truncate(len);
+        this.maze = is_maze(&this.description);
 
         loop {
-            if header == "What do you do?" {
+            if is_prompt(&header) {
                 break;
             }
 
@@ -87,7 +116,9 @@ impl Room {
 
             loop {
                 let mut item = String::new();
-                b.read_line(&mut item)?;
+                if b.read_line(&mut item)? == 0 {
+                    bail!(eyre!("room {:?} ends in the middle of a list", this.title));
+                }
                 if item == "\n" {
                     break;
                 }
@@ -98,9 +129,73 @@ impl Room {
             }
 
             header.clear();
-            b.read_line(&mut header)?;
+            if b.read_line(&mut header)? == 0 {
+                bail!(eyre!("room {:?} ends before {:?}", this.title, PROMPT));
+            }
         }
 
         Ok((prelude, Some(this)))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_maze() {
+        let text = "\
+== Twisty passages ==
+You are in a maze of twisty little passages, all alike.
+
+There are 3 exits:
+- north
+- south
+- west
+
+What do you do?";
+        let (prelude, room) = Room::parse(&mut io::Cursor::new(text.as_bytes().to_vec())).unwrap();
+        let room = room.unwrap();
+
+        assert!(prelude.is_empty());
+        assert_eq!(room.title, "Twisty passages");
+        assert_eq!(room.exits, ["north", "south", "west"]);
+        assert!(room.maze);
+
+        let text =
+            "== Foothills ==\nYou find yourself at the base of a mountain.\n\nWhat do you do?";
+        let (_, room) = Room::parse(&mut io::Cursor::new(text.as_bytes().to_vec())).unwrap();
+        assert!(!room.unwrap().maze);
+    }
+
+    #[test]
+    fn test_parse_paragraphs() {
+        let text = "\
+== Vault Door ==
+You stand before the vault door.  Beside it, a plaque reads:
+
+  Weigh yourself here:
+
+\"The vault is locked.\"
+
+Things of interest here:
+- orb
+
+There is 1 exit:
+- north
+
+What do you do?";
+        let (_, room) = Room::parse(&mut io::Cursor::new(text.as_bytes().to_vec())).unwrap();
+        let room = room.unwrap();
+
+        assert_eq!(
+            room.description,
+            "You stand before the vault door.  Beside it, a plaque reads:\n\n  Weigh yourself here:\n\n\"The vault is locked.\""
+        );
+        assert_eq!(room.items, ["orb"]);
+        assert_eq!(room.exits, ["north"]);
+
+        let truncated = "== Vault Door ==\nYou stand before the vault door.\n\n";
+        assert!(Room::parse(&mut io::Cursor::new(truncated.as_bytes().to_vec())).is_err());
+    }
+}
@@ -0,0 +1,393 @@
+use std::{
+    convert::TryInto,
+    fs,
+    io::{self, Cursor, Read, Write},
+    mem::size_of,
+    path::Path,
+};
+
+#[cfg(not(feature = "compression"))]
+use eyre::eyre;
+use eyre::{bail, Result};
+#[cfg(feature = "compression")]
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+use crate::{program_words, Error, ADDRESS_SPACE, MAX_VALUE, REGISTER_COUNT, VM};
+
+const MAGIC: &[u8; 8] = b"SYNACOR\0";
+const GZIP_MAGIC: &[u8; 2] = b"\x1f\x8b";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = MAGIC.len() + size_of::<u32>();
+const CHECKSUM_LEN: usize = size_of::<u32>();
+
+// memory, registers and pc: the stack can be empty. no program is this long
+const MIN_PAYLOAD_LEN: usize =
+    (ADDRESS_SPACE + REGISTER_COUNT) * size_of::<u32>() + size_of::<usize>();
+
+// read the whole of `path`, or of stdin if it's `-`. stdin is used unlocked,
+// so `input` being a lock on it would deadlock
+fn read_path(path: &Path) -> io::Result<Vec<u8>> {
+    if path == Path::new("-") {
+        let mut contents = Vec::new();
+        io::stdin().read_to_end(&mut contents)?;
+        Ok(contents)
+    } else {
+        fs::read(path)
+    }
+}
+
+/// Load the program at `path`, or on stdin if it's `-`, into a fresh VM,
+/// ready to run.
+pub fn run_program_file<Input: Read, Output: Write>(
+    path: impl AsRef<Path>,
+    input: Input,
+    output: Output,
+) -> Result<Box<VM<Input, Output>>> {
+    VM::load_program_from_reader(input, output, &read_path(path.as_ref())?[..])
+}
+
+/// Load the snapshot at `path`, or on stdin if it's `-`, ready to pick up
+/// where it left off.
+pub fn run_snapshot_file<Input: Read, Output: Write>(
+    path: impl AsRef<Path>,
+    input: Input,
+    output: Output,
+) -> Result<Box<VM<Input, Output>>> {
+    VM::load_snapshot(input, output, &read_path(path.as_ref())?[..])
+}
+
+/// Load `path`, or stdin if it's `-`, as either a snapshot or a program,
+/// telling them apart by the snapshot header, or by their length for
+/// snapshots that predate it.
+pub fn run_file<Input: Read, Output: Write>(
+    path: impl AsRef<Path>,
+    input: Input,
+    output: Output,
+) -> Result<Box<VM<Input, Output>>> {
+    let contents = read_path(path.as_ref())?;
+
+    let has_magic = contents.starts_with(MAGIC) || contents.starts_with(GZIP_MAGIC);
+    if has_magic || contents.len() >= MIN_PAYLOAD_LEN {
+        VM::load_snapshot(input, output, &contents[..])
+    } else {
+        VM::load_program_from_reader(input, output, &contents[..])
+    }
+}
+
+impl VM<Cursor<Vec<u8>>, Cursor<Vec<u8>>> {
+    /// Load a program with both input and output kept in memory, to be fed
+    /// with [`VM::append_input`] and read back from `output`.
+    pub fn in_memory(program: &[u8]) -> Result<Box<Self>> {
+        Self::load_program_from_reader(Cursor::new(Vec::new()), Cursor::new(Vec::new()), program)
+    }
+
+    /// Like [`VM::in_memory`], but resuming from a snapshot.
+    pub fn in_memory_snapshot(r: impl io::Read) -> Result<Box<Self>> {
+        Self::load_snapshot(Cursor::new(Vec::new()), Cursor::new(Vec::new()), r)
+    }
+}
+
+impl<Input: Read, Output: Write> VM<Input, Output> {
+    pub fn load_program_from_reader(
+        input: Input,
+        output: Output,
+        mut r: impl io::Read,
+    ) -> Result<Box<Self>> {
+        let mut program = Vec::new();
+        r.read_to_end(&mut program)?;
+
+        if program.len() % 2 != 0 {
+            bail!(Error::MalformedProgram(program.len()));
+        }
+        if program.len() / 2 > ADDRESS_SPACE {
+            bail!(Error::ProgramTooLarge(program.len() / 2));
+        }
+
+        Ok(Self::from_words(input, output, program_words(&program)))
+    }
+
+    pub fn save_snapshot(&self, mut w: impl io::Write) -> Result<()> {
+        // every write goes through the checksum too
+        let mut crc = crc32fast::Hasher::new();
+        let mut put = |buf: &[u8]| {
+            crc.update(buf);
+            w.write_all(buf)
+        };
+
+        // header
+        put(MAGIC)?;
+        put(&VERSION.to_le_bytes())?;
+
+        // memory: [u32; ADDRESS_SPACE]
+        put(bytemuck::cast_slice(&self.memory))?;
+
+        // registers: [u32; REGISTER_COUNT]
+        put(bytemuck::cast_slice(&self.registers))?;
+
+        // pc: usize,
+        put(&self.pc.to_ne_bytes())?;
+
+        // stack: Stack<u32>
+        put(bytemuck::cast_slice(&self.stack))?;
+
+        // checksum of everything above
+        w.write_all(&crc.finalize().to_le_bytes())?;
+
+        Ok(())
+    }
+
+    /// Like [`VM::save_snapshot`], but gzip-compressed. Most of memory is
+    /// zeroes, so this is a lot smaller.
+    #[cfg(feature = "compression")]
+    pub fn save_snapshot_compressed(&self, w: impl io::Write) -> Result<()> {
+        let mut encoder = GzEncoder::new(w, Compression::default());
+        self.save_snapshot(&mut encoder)?;
+        encoder.finish()?;
+        Ok(())
+    }
+
+    pub fn load_snapshot(input: Input, output: Output, r: impl io::Read) -> Result<Box<Self>> {
+        let mut this = Self::zeroed(input, output);
+        this.load_snapshot_inplace(r)?;
+        Ok(this)
+    }
+
+    /// Load a snapshot over this VM, decompressing it if need be. Snapshots
+    /// from before the header and checksum were added load too, just
+    /// without any checking.
+    pub fn load_snapshot_inplace(&mut self, mut r: impl io::Read) -> Result<()> {
+        let mut snapshot = Vec::new();
+        r.read_to_end(&mut snapshot)?;
+
+        if snapshot.starts_with(GZIP_MAGIC) {
+            #[cfg(feature = "compression")]
+            {
+                let mut decompressed = Vec::new();
+                GzDecoder::new(&snapshot[..]).read_to_end(&mut decompressed)?;
+                snapshot = decompressed;
+            }
+
+            #[cfg(not(feature = "compression"))]
+            bail!(eyre!("compressed snapshots need the `compression` feature"));
+        }
+
+        if !snapshot.starts_with(MAGIC) {
+            return self.load_payload(&snapshot);
+        }
+
+        if snapshot.len() < HEADER_LEN + MIN_PAYLOAD_LEN + CHECKSUM_LEN {
+            bail!(Error::CorruptSnapshot);
+        }
+
+        let (contents, checksum) = snapshot.split_at(snapshot.len() - CHECKSUM_LEN);
+        if crc32fast::hash(contents).to_le_bytes() != checksum {
+            bail!(Error::CorruptSnapshot);
+        }
+
+        let version = u32::from_le_bytes(contents[MAGIC.len()..HEADER_LEN].try_into().unwrap());
+        if version != VERSION {
+            bail!(Error::UnsupportedSnapshotVersion(version));
+        }
+
+        self.load_payload(&contents[HEADER_LEN..])
+    }
+
+    fn load_payload(&mut self, mut r: &[u8]) -> Result<()> {
+        // everything is read and checked before any of it goes into the VM,
+        // so that a snapshot that doesn't load leaves it as it was
+
+        // memory: [u32; ADDRESS_SPACE]
+        let mut memory = vec![0; ADDRESS_SPACE];
+        r.read_exact(bytemuck::cast_slice_mut(&mut memory))?;
+
+        // registers: [u32; REGISTER_COUNT]
+        let mut registers = [0; REGISTER_COUNT];
+        r.read_exact(bytemuck::cast_slice_mut(&mut registers))?;
+
+        // pc: usize,
+        let mut pc_bytes = [0; size_of::<usize>()];
+        r.read_exact(&mut pc_bytes)?;
+        let pc = usize::from_ne_bytes(pc_bytes);
+
+        // stack: Stack<u32>
+        let mut stack = Vec::new();
+        let mut tos_bytes = [0; size_of::<u32>()];
+        while let Ok(()) = r.read_exact(&mut tos_bytes) {
+            stack.push(u32::from_ne_bytes(tos_bytes));
+        }
+
+        // nothing the VM does leaves these out of range, so a snapshot that
+        // has them was mangled, and running it would only fail much later
+        if pc >= ADDRESS_SPACE
+            || registers.iter().any(|&value| value >= MAX_VALUE)
+            || stack.iter().any(|&value| value >= MAX_VALUE)
+        {
+            bail!(Error::CorruptSnapshot);
+        }
+
+        self.memory.copy_from_slice(&memory);
+        self.registers = registers;
+        self.pc = pc;
+        self.stack = stack;
+        self.invalidate_decode_cache();
+
+        // wherever the snapshot is, it's not where we were
+        self.current_room = None;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn test_roundtrip(
+            memory in vec![any::<u32>(); ADDRESS_SPACE],
+            registers in vec![0..MAX_VALUE; REGISTER_COUNT],
+            stack in prop::collection::vec(0..MAX_VALUE, 0..100),
+            pc in 0..ADDRESS_SPACE,
+        ) {
+            let memory = <[u32; ADDRESS_SPACE]>::try_from(memory).unwrap();
+            let registers = <[u32; REGISTER_COUNT]>::try_from(registers).unwrap();
+
+            let mut vm = VM::from_words(io::Cursor::new(vec![]), io::Cursor::new(vec![]), memory);
+            vm.registers = registers;
+            vm.stack = stack;
+            vm.pc = pc;
+
+            let mut buf = Vec::new();
+            vm.save_snapshot(&mut buf).unwrap();
+            prop_assert_eq!(
+                VM::load_snapshot(
+                    io::Cursor::new(vec![]),
+                    io::Cursor::new(vec![]),
+                    io::Cursor::new(buf)
+                ).unwrap(),
+                vm
+            );
+        }
+    }
+
+    #[test]
+    fn test_in_memory() {
+        // out 'A'; halt
+        let mut vm = VM::in_memory(&[19, 0, 65, 0, 0, 0]).unwrap();
+        vm.cycle().unwrap();
+
+        let mut snapshot = Vec::new();
+        vm.save_snapshot(&mut snapshot).unwrap();
+        let mut resumed = VM::in_memory_snapshot(&snapshot[..]).unwrap();
+        resumed.run_to_halt().unwrap();
+
+        assert_eq!(vm.output.get_ref(), b"A");
+        assert_eq!(resumed.pc, 2);
+        assert!(resumed.output.get_ref().is_empty());
+    }
+
+    #[test]
+    fn test_corrupt_snapshot() {
+        let mut vm = VM::from_words(io::Cursor::new(vec![]), io::sink(), [19, 65, 0]);
+        vm.stack = vec![1, 2, 3];
+
+        let mut snapshot = Vec::new();
+        vm.save_snapshot(&mut snapshot).unwrap();
+
+        let load = |snapshot: &[u8]| {
+            VM::load_snapshot(io::Cursor::new(vec![]), io::sink(), snapshot).unwrap_err()
+        };
+
+        // flip a byte in the memory
+        let mut flipped = snapshot.clone();
+        flipped[HEADER_LEN + 4] ^= 0xff;
+        let err = load(&flipped);
+        assert!(matches!(err.downcast_ref(), Some(Error::CorruptSnapshot)));
+
+        // lose the end of the stack, as a crash halfway through saving would
+        let err = load(&snapshot[..snapshot.len() - 6]);
+        assert!(matches!(err.downcast_ref(), Some(Error::CorruptSnapshot)));
+    }
+
+    #[test]
+    fn test_out_of_range_snapshot() {
+        let load = |vm: &VM<io::Cursor<Vec<u8>>, io::Sink>| {
+            let mut snapshot = Vec::new();
+            vm.save_snapshot(&mut snapshot).unwrap();
+            VM::load_snapshot(io::Cursor::new(vec![]), io::sink(), &snapshot[..]).unwrap_err()
+        };
+
+        let mut vm = VM::from_words(io::Cursor::new(vec![]), io::sink(), [19, 65, 0]);
+        vm.pc = ADDRESS_SPACE;
+        let err = load(&vm);
+        assert!(matches!(err.downcast_ref(), Some(Error::CorruptSnapshot)));
+
+        vm.pc = 0;
+        vm.registers[3] = MAX_VALUE + 7;
+        let err = load(&vm);
+        assert!(matches!(err.downcast_ref(), Some(Error::CorruptSnapshot)));
+    }
+
+    #[test]
+    fn test_rejected_load_leaves_vm_alone() {
+        let mut vm = VM::from_words(
+            io::Cursor::new(vec![]),
+            io::Cursor::new(vec![]),
+            [19, 65, 0],
+        );
+        vm.registers[2] = 5;
+        vm.stack.push(9);
+        vm.enable_decode_cache();
+
+        let mut bad = VM::from_words(io::Cursor::new(vec![]), io::sink(), [21, 0]);
+        bad.registers[0] = MAX_VALUE;
+        let mut snapshot = Vec::new();
+        bad.save_snapshot(&mut snapshot).unwrap();
+
+        let before = vm.clone();
+        assert!(vm.load_snapshot_inplace(&snapshot[..]).is_err());
+        // a legacy snapshot cut off partway through its registers
+        let truncated = &snapshot[HEADER_LEN..HEADER_LEN + ADDRESS_SPACE * 4 + 8];
+        assert!(vm.load_snapshot_inplace(truncated).is_err());
+        assert_eq!(vm, before);
+    }
+
+    #[test]
+    fn test_malformed_program() {
+        let load = |program: &[u8]| {
+            VM::load_program_from_reader(io::empty(), io::sink(), program).unwrap_err()
+        };
+
+        let err = load(&[0x13, 0x00, 0x41]);
+        assert!(matches!(err.downcast_ref(), Some(Error::MalformedProgram(3))));
+
+        let err = load(&vec![0; 2 * ADDRESS_SPACE + 2]);
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(Error::ProgramTooLarge(len)) if *len == ADDRESS_SPACE + 1
+        ));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compressed_roundtrip() {
+        let mut vm = VM::from_words(io::Cursor::new(vec![]), io::sink(), [19, 65, 0]);
+        vm.stack = vec![1, 2, 3];
+
+        let mut plain = Vec::new();
+        vm.save_snapshot(&mut plain).unwrap();
+        let mut compressed = Vec::new();
+        vm.save_snapshot_compressed(&mut compressed).unwrap();
+        assert!(compressed.len() * 10 < plain.len());
+
+        let loaded =
+            VM::load_snapshot(io::Cursor::new(vec![]), io::sink(), &compressed[..]).unwrap();
+        assert_eq!(loaded.memory, vm.memory);
+        assert_eq!(loaded.stack, vm.stack);
+    }
+}
@@ -0,0 +1,52 @@
+use super::puzzles::solve_expression;
+
+const COINS: [i64; 5] = [2, 3, 5, 7, 9];
+const COIN_NAMES: [&str; 10] = [
+    "",
+    "",
+    "red coin",
+    "corroded coin",
+    "",
+    "shiny coin",
+    "",
+    "concave coin",
+    "",
+    "blue coin",
+];
+const TARGET: i64 = 399;
+
+/// Find the order in which the coins must be placed in the ruins.
+pub fn solve_coins() -> [&'static str; 5] {
+    // _ + _ * _^2 + _^3 - _ = 399
+    let coins = solve_expression(
+        &COINS,
+        |&[a, b, c, d, e]| a + b * c * c + d * d * d - e,
+        TARGET,
+    )
+    .expect("no order of the coins adds up");
+
+    let mut names = [""; 5];
+    for (name, &coin) in names.iter_mut().zip(coins.iter()) {
+        *name = COIN_NAMES[coin as usize];
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_solve_coins() {
+        assert_eq!(
+            solve_coins(),
+            [
+                "blue coin",
+                "red coin",
+                "shiny coin",
+                "concave coin",
+                "corroded coin"
+            ]
+        );
+    }
+}
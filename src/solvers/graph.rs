@@ -0,0 +1,130 @@
+//! Shortest-path searches over graphs that are only known through a
+//! function giving each node's neighbors, like the states of a puzzle.
+
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::Entry, HashMap, VecDeque},
+    hash::Hash,
+};
+
+use priority_queue::PriorityQueue;
+
+// follow the breadcrumbs in `prev` from `goal` back to the start
+fn path<N: Clone + Eq + Hash>(prev: &HashMap<N, Option<N>>, goal: N) -> Vec<N> {
+    let mut path = vec![goal];
+    while let Some(Some(node)) = prev.get(path.last().unwrap()) {
+        path.push(node.clone());
+    }
+    path.reverse();
+    path
+}
+
+/// Find a path with the fewest steps from `start` to a node satisfying
+/// `goal`, both ends included.
+pub fn bfs<N, I>(
+    start: N,
+    mut neighbors: impl FnMut(&N) -> I,
+    mut goal: impl FnMut(&N) -> bool,
+) -> Option<Vec<N>>
+where
+    N: Clone + Eq + Hash,
+    I: IntoIterator<Item = N>,
+{
+    let mut prev = HashMap::new();
+    prev.insert(start.clone(), None);
+    let mut queue = VecDeque::from([start]);
+
+    while let Some(node) = queue.pop_front() {
+        if goal(&node) {
+            return Some(path(&prev, node));
+        }
+
+        for next in neighbors(&node) {
+            if let Entry::Vacant(entry) = prev.entry(next.clone()) {
+                entry.insert(Some(node.clone()));
+                queue.push_back(next);
+            }
+        }
+    }
+
+    None
+}
+
+/// Find the cheapest path from `start` to a node satisfying `goal`, given
+/// each neighbor along with the cost of getting there. Returns the path,
+/// both ends included, and its cost.
+pub fn dijkstra<N, I>(
+    start: N,
+    mut neighbors: impl FnMut(&N) -> I,
+    mut goal: impl FnMut(&N) -> bool,
+) -> Option<(Vec<N>, u64)>
+where
+    N: Clone + Eq + Hash,
+    I: IntoIterator<Item = (N, u64)>,
+{
+    let mut dist = HashMap::new();
+    dist.insert(start.clone(), 0);
+    let mut prev = HashMap::new();
+    prev.insert(start.clone(), None);
+
+    // Reverse() makes the queue hand out the closest node first
+    let mut queue = PriorityQueue::new();
+    queue.push(start, Reverse(0));
+
+    while let Some((node, Reverse(cost))) = queue.pop() {
+        if goal(&node) {
+            return Some((path(&prev, node), cost));
+        }
+
+        for (next, step) in neighbors(&node) {
+            let alt = cost + step;
+            if dist.get(&next).is_none_or(|&known| alt < known) {
+                dist.insert(next.clone(), alt);
+                prev.insert(next.clone(), Some(node.clone()));
+                queue.push(next, Reverse(alt));
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //   a --1-- b --1-- c --1-- d
+    //    \                     /
+    //     `--------10---------'
+    //   e, all on its own
+    fn edges(node: &char) -> Vec<(char, u64)> {
+        match node {
+            'a' => vec![('b', 1), ('d', 10)],
+            'b' => vec![('a', 1), ('c', 1)],
+            'c' => vec![('b', 1), ('d', 1)],
+            'd' => vec![('c', 1), ('a', 10)],
+            _ => vec![],
+        }
+    }
+
+    fn neighbors(node: &char) -> Vec<char> {
+        edges(node).into_iter().map(|(next, _)| next).collect()
+    }
+
+    #[test]
+    fn test_bfs() {
+        assert_eq!(bfs('a', neighbors, |&n| n == 'd'), Some(vec!['a', 'd']));
+        assert_eq!(bfs('b', neighbors, |&n| n == 'b'), Some(vec!['b']));
+        assert_eq!(bfs('a', neighbors, |&n| n == 'e'), None);
+    }
+
+    #[test]
+    fn test_dijkstra() {
+        assert_eq!(
+            dijkstra('a', edges, |&n| n == 'd'),
+            Some((vec!['a', 'b', 'c', 'd'], 3))
+        );
+        assert_eq!(dijkstra('c', edges, |&n| n == 'c'), Some((vec!['c'], 0)));
+        assert_eq!(dijkstra('a', edges, |&n| n == 'e'), None);
+    }
+}
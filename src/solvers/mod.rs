@@ -0,0 +1,31 @@
+//! The puzzle solvers, as library functions so that both the per-stage bins
+//! and the `solve` orchestrator can drive them.
+
+use std::io::Cursor;
+
+pub mod coins;
+pub mod graph;
+pub mod puzzles;
+pub mod stages;
+// the teleporter solver needs rayon's threads, which wasm doesn't have
+#[cfg(not(target_arch = "wasm32"))]
+pub mod teleporter;
+pub mod twisty;
+pub mod vault;
+
+type VM = crate::VM<Cursor<Vec<u8>>, Cursor<Vec<u8>>>;
+
+/// How far along a long-running solver is, as given to the callbacks of the
+/// `*_with_progress` functions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Progress {
+    /// `tried` of the `total` candidates have been checked.
+    Candidates { tried: u64, total: u64 },
+
+    /// This many rooms have been explored so far.
+    Rooms(usize),
+}
+
+// what the `*_with_progress` functions pass around, `None` when nobody's
+// listening so that the hot loops can skip the bookkeeping
+type ProgressCallback<'a> = Option<&'a (dyn Fn(Progress) + Sync)>;
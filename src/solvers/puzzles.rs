@@ -0,0 +1,96 @@
+//! Searches shared by the puzzles that come down to putting some values in
+//! the right order.
+
+use std::convert::TryInto;
+
+/// Every ordering of `items`, starting with `items` as they are, made one
+/// swap at a time with Heap's algorithm.
+pub fn permutations<T: Clone>(items: &[T]) -> impl Iterator<Item = Vec<T>> {
+    let mut arr = items.to_vec();
+    let n = arr.len();
+
+    // Heap's algorithm without recursing: `p` stands in for the stack of
+    // loop counters, and `idx` for how deep the next swap is
+    let mut p = (0..=n).collect::<Vec<_>>();
+    let mut idx = 1;
+    let mut first = true;
+
+    std::iter::from_fn(move || {
+        if first {
+            first = false;
+            return Some(arr.clone());
+        }
+        if idx >= n {
+            return None;
+        }
+
+        p[idx] -= 1;
+        let j = if idx % 2 == 1 { p[idx] } else { 0 };
+        arr.swap(idx, j);
+
+        idx = 1;
+        while p[idx] == 0 {
+            p[idx] = idx;
+            idx += 1;
+        }
+
+        Some(arr.clone())
+    })
+}
+
+/// Find an ordering of `values` that makes `eval` come out as `target`.
+/// There have to be exactly `N` values.
+pub fn solve_expression<const N: usize>(
+    values: &[i64],
+    eval: impl Fn(&[i64; N]) -> i64,
+    target: i64,
+) -> Option<Vec<i64>> {
+    let arr: [i64; N] = values.try_into().ok()?;
+    permutations(&arr).find(|ordering| eval(&ordering[..].try_into().unwrap()) == target)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_permutations() {
+        for n in 0..=5 {
+            let items = (0..n).collect::<Vec<_>>();
+            let all = permutations(&items).collect::<Vec<_>>();
+            assert_eq!(all.len(), (1..=n).product::<usize>());
+            assert_eq!(all[0], items);
+
+            let distinct = all.iter().collect::<HashSet<_>>();
+            assert_eq!(distinct.len(), all.len());
+            for ordering in &all {
+                let mut sorted = ordering.clone();
+                sorted.sort_unstable();
+                assert_eq!(sorted, items);
+            }
+        }
+    }
+
+    #[test]
+    fn test_solve_expression() {
+        let values = [1, 2, 3, 4];
+        let eval = |&[a, b, c, d]: &[i64; 4]| a * b - c * d;
+
+        let solution = solve_expression(&values, eval, 10).unwrap();
+        assert_eq!(eval(&solution[..].try_into().unwrap()), 10);
+        let mut sorted = solution.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, values);
+
+        // 3 * 4 - 1 * 2 is as big as it gets
+        assert_eq!(solve_expression(&values, eval, 11), None);
+        assert_eq!(solve_expression(&values[..3], eval, 10), None);
+
+        assert_eq!(
+            solve_expression(&[7], |&[a]: &[i64; 1]| a, 7),
+            Some(vec![7])
+        );
+    }
+}
@@ -0,0 +1,160 @@
+//! The snapshots the solvers start from and leave behind, one per point in
+//! the game where a puzzle begins or has just been solved.
+
+use std::{
+    fs,
+    io::{BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use eyre::{bail, eyre, Result, WrapErr};
+
+use super::VM;
+
+/// Where the stage snapshots live, relative to the working directory.
+pub const SNAPSHOT_DIR: &str = "snapshots";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Stage {
+    /// Just arrived in the twisty passages, with the lantern still unlit.
+    TwistyPassages,
+
+    /// Back in the twisty passages with the lantern lit.
+    LitLantern,
+
+    /// Standing next to the teleporter.
+    Teleporter,
+
+    /// Teleported with r7 set right.
+    TeleporterPatched,
+
+    /// In the vault antechamber, before taking the orb.
+    Vault,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] = [
+        Stage::TwistyPassages,
+        Stage::LitLantern,
+        Stage::Teleporter,
+        Stage::TeleporterPatched,
+        Stage::Vault,
+    ];
+
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Stage::TwistyPassages => "00_twistypassages.snapshot.bin",
+            Stage::LitLantern => "01_lit_lantern.snapshot.bin",
+            Stage::Teleporter => "03_teleporter.snapshot.bin",
+            Stage::TeleporterPatched => "04_teleporter_patched.snapshot.bin",
+            Stage::Vault => "05_vault.snapshot.bin",
+        }
+    }
+
+    /// The stage's snapshot in [`SNAPSHOT_DIR`].
+    pub fn path(self) -> PathBuf {
+        Path::new(SNAPSHOT_DIR).join(self.file_name())
+    }
+}
+
+/// Load the snapshot for `stage` from [`SNAPSHOT_DIR`], with no input queued
+/// up and the output kept in memory.
+pub fn load_stage(stage: Stage) -> Result<Box<VM>> {
+    load_stage_in(SNAPSHOT_DIR, stage)
+}
+
+pub fn save_stage<Input: Read, Output: Write>(
+    vm: &crate::VM<Input, Output>,
+    stage: Stage,
+) -> Result<()> {
+    save_stage_in(SNAPSHOT_DIR, vm, stage)
+}
+
+/// Like [`load_stage`], but looking in `dir` instead.
+pub fn load_stage_in(dir: impl AsRef<Path>, stage: Stage) -> Result<Box<VM>> {
+    load_stage_from(dir.as_ref().join(stage.file_name()), stage)
+}
+
+/// Like [`load_stage`], but loading whatever's at `path`, which is
+/// supposed to be `stage`'s snapshot.
+pub fn load_stage_from(path: impl AsRef<Path>, stage: Stage) -> Result<Box<VM>> {
+    let path = path.as_ref();
+    if !path.exists() {
+        bail!(eyre!(
+            "there's no snapshot at {}, give the path to the {:?} snapshot instead",
+            path.display(),
+            stage
+        ));
+    }
+
+    let file =
+        fs::File::open(path).wrap_err_with(|| format!("couldn't open {}", path.display()))?;
+    VM::in_memory_snapshot(BufReader::new(file))
+        .wrap_err_with(|| format!("couldn't load {}", path.display()))
+}
+
+/// Like [`save_stage`], but saving to `dir` instead.
+pub fn save_stage_in<Input: Read, Output: Write>(
+    dir: impl AsRef<Path>,
+    vm: &crate::VM<Input, Output>,
+    stage: Stage,
+) -> Result<()> {
+    save_stage_to(dir.as_ref().join(stage.file_name()), vm)
+}
+
+/// Save `vm` at `path`, wherever that may be.
+pub fn save_stage_to<Input: Read, Output: Write>(
+    path: impl AsRef<Path>,
+    vm: &crate::VM<Input, Output>,
+) -> Result<()> {
+    let path = path.as_ref();
+    let file =
+        fs::File::create(path).wrap_err_with(|| format!("couldn't create {}", path.display()))?;
+    vm.save_snapshot(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_stages() {
+        assert_eq!(
+            Stage::Vault.path(),
+            Path::new("snapshots/05_vault.snapshot.bin")
+        );
+        let mut names = Stage::ALL.map(Stage::file_name).to_vec();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), Stage::ALL.len());
+
+        // out 'A'; halt
+        let mut vm = VM::from_words(
+            Cursor::new(Vec::new()),
+            Cursor::new(Vec::new()),
+            vec![19, 65, 0],
+        );
+        vm.cycle().unwrap();
+        vm.registers[3] = 42;
+        vm.stack.push(7);
+
+        let dir = std::env::temp_dir().join(format!("synacor-stages-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        save_stage_in(&dir, &vm, Stage::Teleporter).unwrap();
+        let loaded = load_stage_in(&dir, Stage::Teleporter).unwrap();
+        let missing = load_stage_in(&dir, Stage::Vault).unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(
+            missing.to_string().contains("Vault snapshot"),
+            "{}",
+            missing
+        );
+
+        assert_eq!(loaded.pc, vm.pc);
+        assert_eq!(loaded.registers, vm.registers);
+        assert_eq!(loaded.stack, vm.stack);
+        assert_eq!(loaded.memory[..], vm.memory[..]);
+    }
+}
@@ -0,0 +1,491 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use eyre::{bail, eyre, Result};
+use rayon::prelude::*;
+
+use super::{Progress, ProgressCallback, VM};
+use crate::{Register, MAX_VALUE};
+
+// how many cycles the confirmation call gets when run for real, with the
+// routine's answers remembered
+const CONFIRM_CYCLES: u64 = 50_000_000;
+
+// every possible value of r7
+const CANDIDATES: u32 = MAX_VALUE;
+
+// what the confirmation routine's arithmetic wraps around at
+const MODULUS: u32 = MAX_VALUE;
+
+// how many cycles probing the confirmation routine with tiny arguments gets
+const PROBE_CYCLES: u64 = 1_000_000;
+
+// how many candidates get tried between progress reports
+const PROGRESS_INTERVAL: u64 = 1024;
+
+// x^y, modulo m
+fn powmod(x: u32, mut y: u32, m: u32) -> u32 {
+    let mut t = 1;
+    let mut tmp = x % m;
+    while y > 0 {
+        if y & 1 > 0 {
+            t = t * tmp % m;
+        }
+
+        tmp = (tmp * tmp) % m;
+        y >>= 1;
+    }
+    t
+}
+
+// 1 + x + x^2 + ... + x^(n - 1), modulo m, halving n rather than adding up
+// all n terms
+fn geometric_sum(x: u32, n: u32, m: u32) -> u32 {
+    if n == 0 {
+        0
+    } else if n % 2 == 1 {
+        (1 + x % m * geometric_sum(x, n - 1, m)) % m
+    } else {
+        // the second half of the terms is the first half times x^(n/2)
+        geometric_sum(x, n / 2, m) * (1 + powmod(x, n / 2, m)) % m
+    }
+}
+
+/// The base cases of the confirmation routine, which is Ackermann's function
+/// with a twist: `A(0, n) = n + increment` and `A(m, 0) = A(m - 1, r7)`, all
+/// modulo 32768.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BaseCases {
+    pub increment: u32,
+}
+
+impl BaseCases {
+    /// The base cases of the challenge's routine.
+    pub const CHALLENGE: BaseCases = BaseCases { increment: 1 };
+}
+
+// the confirmation routine as the VM runs it, one step at a time, which takes
+// forever for all but tiny arguments
+fn naive_ackermann(base: BaseCases, r7: u32, r0: u32, r1: u32) -> u32 {
+    let mut pending = vec![r0];
+    let mut n = r1;
+    while let Some(m) = pending.pop() {
+        if m == 0 {
+            n = (n + base.increment) % MODULUS;
+        } else if n == 0 {
+            pending.push(m - 1);
+            n = r7;
+        } else {
+            pending.push(m - 1);
+            pending.push(m);
+            n -= 1;
+        }
+    }
+    n
+}
+
+struct Ackermann {
+    base: BaseCases,
+    r7: u32,
+    memo: HashMap<(u32, u32), u32>,
+}
+
+impl Ackermann {
+    fn new(base: BaseCases, r7: u32) -> Self {
+        Self {
+            base,
+            r7,
+            memo: HashMap::new(),
+        }
+    }
+
+    fn ack(&mut self, r0: u32, r1: u32) -> u32 {
+        if let Some(&v) = self.memo.get(&(r0, r1)) {
+            return v;
+        }
+
+        let c = self.base.increment;
+        let v = match (r0, r1) {
+            (0, r1) => r1 + c,
+
+            (r0, 0) => self.ack(r0 - 1, self.r7),
+
+            // A(1, n) = A(0, A(1, n - 1)) = A(1, n - 1) + c
+            // A(1, n) = A(1, 0) + n * c
+            (1, r1) => self.ack(1, 0) + r1 * c,
+
+            // A(2, n) = A(1, A(2, n - 1)) = A(1, 0) + c * A(2, n - 1)
+            // A(2, n) = c^n * A(2, 0) + (1 + c + ... + c^(n - 1)) * A(1, 0)
+            (2, r1) => {
+                powmod(c, r1, MODULUS) * self.ack(2, 0)
+                    + geometric_sum(c, r1, MODULUS) * self.ack(1, 0)
+            }
+
+            // with c = 1, A(2, n) = A(2, 0) + n * A(1, 0), so
+            // A(3, n) = A(2, A(3, n - 1)) = A(2, 0) + A(1, 0) * A(3, n - 1)
+            // A(3, n) = A(1, 0)^n * A(3, 0) + (1 + ... + A(1, 0)^(n - 1)) * A(2, 0)
+            (3, r1) if c == 1 => {
+                let a = self.ack(1, 0);
+                powmod(a, r1, MODULUS) * self.ack(3, 0)
+                    + geometric_sum(a, r1, MODULUS) * self.ack(2, 0)
+            }
+
+            (r0, r1) => {
+                let y = self.ack(r0, r1 - 1);
+                self.ack(r0 - 1, y)
+            }
+        } % MODULUS;
+
+        #[cfg(debug_assertions)]
+        self.check(r0, r1, v);
+
+        self.memo.insert((r0, r1), v);
+        v
+    }
+
+    // the shortcuts are only as good as the algebra behind them, so check
+    // them where that's cheap: against the plain definition while the values
+    // stay small, and against the recurrence for A(3, n)
+    #[cfg(debug_assertions)]
+    fn check(&mut self, r0: u32, r1: u32, v: u32) {
+        match r0 {
+            1 | 2 if self.r7 < 64 && r1 < 4 => {
+                debug_assert_eq!(v, naive_ackermann(self.base, self.r7, r0, r1));
+            }
+            3 if (1..4).contains(&r1) => {
+                let y = self.ack(3, r1 - 1);
+                debug_assert_eq!(v, self.ack(2, y));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Find the first value of r7 for which the confirmation routine, called with
+/// `r0` and `r1`, returns `target`.
+pub fn find_teleporter_register(base: BaseCases, r0: u32, r1: u32, target: u32) -> Option<u32> {
+    search_teleporter_register(base, r0, r1, target, None)
+}
+
+/// Like [`find_teleporter_register`], but reporting how many candidates have
+/// been tried every so often. The callback is called from rayon's threads.
+pub fn find_teleporter_register_with_progress(
+    base: BaseCases,
+    r0: u32,
+    r1: u32,
+    target: u32,
+    progress: impl Fn(Progress) + Sync,
+) -> Option<u32> {
+    search_teleporter_register(base, r0, r1, target, Some(&progress))
+}
+
+fn search_teleporter_register(
+    base: BaseCases,
+    r0: u32,
+    r1: u32,
+    target: u32,
+    progress: ProgressCallback,
+) -> Option<u32> {
+    let tried = AtomicU64::new(0);
+
+    (0..CANDIDATES).into_par_iter().find_first(|&r7| {
+        let found = Ackermann::new(base, r7).ack(r0, r1) == target;
+
+        if let Some(progress) = progress {
+            let tried = tried.fetch_add(1, Ordering::Relaxed) + 1;
+            if tried.is_multiple_of(PROGRESS_INTERVAL) {
+                progress(Progress::Candidates {
+                    tried,
+                    total: CANDIDATES.into(),
+                });
+            }
+        }
+
+        found
+    })
+}
+
+/// Starting from a VM standing next to the teleporter, use it while skipping
+/// the confirmation routine and set r7 so that the teleportation succeeds.
+pub fn patch_teleporter<Output: Write>(
+    vm: &mut crate::VM<io::Cursor<Vec<u8>>, Output>,
+) -> Result<()> {
+    patch(vm, None)
+}
+
+/// Like [`patch_teleporter`], reporting progress through the search for r7.
+pub fn patch_teleporter_with_progress<Output: Write>(
+    vm: &mut crate::VM<io::Cursor<Vec<u8>>, Output>,
+    progress: impl Fn(Progress) + Sync,
+) -> Result<()> {
+    patch(vm, Some(&progress))
+}
+
+fn patch<Output: Write>(
+    vm: &mut crate::VM<io::Cursor<Vec<u8>>, Output>,
+    progress: ProgressCallback,
+) -> Result<()> {
+    // Set register 7 to a bogus value
+    vm.set_register(Register::R7, 0xCA);
+
+    // Use the teleporter and cycle until the ackermann test
+    vm.append_input("use teleporter\n")?;
+    while vm.pc != 5483 {
+        vm.cycle()?;
+    }
+
+    // Fetch the ackermann parameters
+    let r0 = vm.memory[vm.pc + 2];
+    let r1 = vm.memory[vm.pc + 3 + 2];
+
+    // Work out how the ackermann function itself behaves
+    let routine = vm.memory[vm.pc + 3 + 3 + 1] as usize;
+    let base = probe_base_cases(vm, routine)?;
+
+    // See what we're comparing to, right after the call
+    let after_call = vm.pc + 3 + 3 + 2;
+    let target = vm.memory[after_call + 3];
+
+    // Calculate the correct r7
+    let r7 =
+        search_teleporter_register(base, r0, r1, target, progress).ok_or_else(|| eyre!("no r7"))?;
+
+    // Make sure the game's own routine agrees before skipping it
+    let result = run_confirmation(vm, routine, after_call, r7)?;
+    if result != target {
+        bail!(eyre!(
+            "miscalibration: with r7 = {} the confirmation routine returns {}, not {}",
+            r7,
+            result,
+            target
+        ));
+    }
+
+    // Skip the call to the ackermann function and set the registers appropiately
+    vm.set_pc(after_call)?;
+    vm.set_register(Register::R0, target);
+    vm.set_register(Register::R7, r7);
+    Ok(())
+}
+
+// a copy of `vm` with its own output, as `Output` can't necessarily be cloned
+fn detached_copy<Output: Write>(
+    vm: &crate::VM<io::Cursor<Vec<u8>>, Output>,
+) -> Result<Box<VM>> {
+    let mut snapshot = Vec::new();
+    vm.save_snapshot(&mut snapshot)?;
+
+    crate::VM::load_snapshot(
+        vm.input.clone(),
+        io::Cursor::new(Vec::new()),
+        io::Cursor::new(snapshot),
+    )
+}
+
+/// Work out the base cases of the confirmation routine at `routine` by
+/// calling it with tiny arguments on copies of `vm`, and make sure it's the
+/// kind of function [`BaseCases`] describes.
+fn probe_base_cases<Output: Write>(
+    vm: &crate::VM<io::Cursor<Vec<u8>>, Output>,
+    routine: usize,
+) -> Result<BaseCases> {
+    let call = |r0, r1, r7| -> Result<u32> {
+        let mut copy = detached_copy(vm)?;
+        copy.set_register(Register::R0, r0);
+        copy.set_register(Register::R1, r1);
+        copy.set_register(Register::R7, r7);
+
+        // return to where we are now, and stop there
+        let (home, depth) = (copy.pc, copy.stack.len());
+        copy.stack.push(home as u32);
+        copy.set_pc(routine)?;
+
+        for _ in 0..PROBE_CYCLES {
+            copy.cycle()?;
+            if copy.pc == home && copy.stack.len() == depth {
+                return Ok(copy.register(Register::R0));
+            }
+        }
+        bail!(eyre!("the confirmation routine didn't return"))
+    };
+
+    let base = BaseCases {
+        increment: call(0, 0, 0)?,
+    };
+    for &(r0, r1, r7) in &[(1, 0, 5), (1, 2, 3), (2, 1, 2)] {
+        if call(r0, r1, r7)? != naive_ackermann(base, r7, r0, r1) {
+            bail!(eyre!(
+                "the confirmation routine doesn't look like ackermann's function"
+            ));
+        }
+    }
+    Ok(base)
+}
+
+/// Make the confirmation call `vm` is about to make on a copy of it with r7
+/// set to `r7`, and return the r0 it comes back to `after_call` with. Run as
+/// is it would take ages, so calls to `routine` with arguments it's already
+/// been called with are answered from memory, which [`probe_base_cases`]
+/// checked is fine: the routine only depends on r0 and r1.
+fn run_confirmation<Output: Write>(
+    vm: &crate::VM<io::Cursor<Vec<u8>>, Output>,
+    routine: usize,
+    after_call: usize,
+    r7: u32,
+) -> Result<u32> {
+    let mut copy = detached_copy(vm)?;
+    copy.set_register(Register::R7, r7);
+    let depth = copy.stack.len();
+
+    let mut known = HashMap::new();
+    // the calls still running: their arguments, and how deep the stack is
+    // once they've returned
+    let mut running: Vec<((u32, u32), usize)> = Vec::new();
+
+    for _ in 0..CONFIRM_CYCLES {
+        if copy.pc == after_call && copy.stack.len() == depth {
+            return Ok(copy.register(Register::R0));
+        }
+
+        if copy.pc == routine {
+            if copy.stack.is_empty() {
+                bail!(eyre!("the confirmation routine was jumped to, not called"));
+            }
+
+            let args = (copy.register(Register::R0), copy.register(Register::R1));
+            if let Some(&result) = known.get(&args) {
+                // return straight away, as `ret` would
+                copy.set_register(Register::R0, result);
+                let address = copy.stack.pop().unwrap();
+                copy.set_pc(address as usize)?;
+                continue;
+            }
+            running.push((args, copy.stack.len() - 1));
+        }
+
+        copy.cycle()?;
+
+        while let Some(&(args, returned)) = running.last() {
+            if copy.stack.len() > returned {
+                break;
+            }
+            known.insert(args, copy.register(Register::R0));
+            running.pop();
+        }
+    }
+
+    bail!(eyre!(
+        "the confirmation call didn't return within {} cycles",
+        CONFIRM_CYCLES
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // from the start of the game to picking up the teleporter in the ruins
+    const TO_THE_TELEPORTER: &str = "\
+take tablet
+doorway
+north
+north
+bridge
+continue
+down
+east
+take empty lantern
+west
+west
+passage
+ladder
+west
+south
+north
+take can
+use can
+use lantern
+west
+ladder
+darkness
+continue
+west
+west
+west
+west
+north
+take red coin
+north
+east
+take concave coin
+down
+take corroded coin
+up
+west
+west
+take blue coin
+up
+take shiny coin
+down
+east
+use blue coin
+use red coin
+use shiny coin
+use concave coin
+use corroded coin
+north
+take teleporter
+";
+
+    #[test]
+    fn test_run_confirmation() {
+        let mut vm = VM::in_memory(include_bytes!("../challenge.bin")).unwrap();
+        vm.cycle_until_next_room().unwrap();
+        for command in TO_THE_TELEPORTER.lines() {
+            vm.send_command(command).unwrap();
+        }
+
+        vm.set_register(Register::R7, 1);
+        vm.append_input("use teleporter\n").unwrap();
+        while vm.pc != 5483 {
+            vm.cycle().unwrap();
+        }
+
+        assert_eq!(run_confirmation(&vm, 6027, 5491, 25734).unwrap(), 6);
+        assert_ne!(run_confirmation(&vm, 6027, 5491, 25733).unwrap(), 6);
+    }
+
+    #[test]
+    fn test_ackermann() {
+        for x in 0..5 {
+            for n in 0..20 {
+                let sum = (0..n).map(|i| powmod(x, i, MODULUS)).sum::<u32>() % MODULUS;
+                assert_eq!(geometric_sum(x, n, MODULUS), sum, "x = {}, n = {}", x, n);
+            }
+        }
+
+        // the challenge's increment, which the A(3, n) shortcut relies on,
+        // and one that has to go the long way around
+        for &base in &[BaseCases::CHALLENGE, BaseCases { increment: 2 }] {
+            for r7 in 0..4 {
+                let mut ackermann = Ackermann::new(base, r7);
+                for r0 in 0..=3 {
+                    for r1 in 0..if r0 == 3 { 2 } else { 4 } {
+                        assert_eq!(
+                            ackermann.ack(r0, r1),
+                            naive_ackermann(base, r7, r0, r1),
+                            "{:?}, r7 = {}: A({}, {})",
+                            base,
+                            r7,
+                            r0,
+                            r1
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::HashSet;
+
+use eyre::{bail, eyre, Result};
+
+use super::{Progress, ProgressCallback, VM};
+use crate::Room;
+
+/// Whether `exit` keeps to the twisty passages, as the ladder leads back up
+/// out of them. The walkers here take a predicate like this one so that
+/// callers decide where the region of interest ends.
+pub fn in_passages(_room: &Room, exit: &str) -> bool {
+    exit != "ladder"
+}
+
+fn find_can(
+    visited: &mut HashSet<String>,
+    should_follow: &dyn Fn(&Room, &str) -> bool,
+    mut vm: Box<VM>,
+    room: Room,
+) -> Result<Option<Box<VM>>> {
+    if !room.items.is_empty() {
+        debug_assert!(room.items.len() == 1 && room.items[0] == "can");
+        vm.append_input("take can\nuse can\nuse lantern\n")?;
+        return Ok(Some(vm));
+    }
+
+    for exit in &room.exits {
+        if !should_follow(&room, exit) {
+            continue;
+        }
+
+        let mut vm = vm.clone();
+        let next_room = vm.send_command(exit)?.1;
+        if let Some(next_room) = next_room {
+            if visited.insert(next_room.description.clone()) {
+                if let Some(can) = find_can(visited, should_follow, vm, next_room)? {
+                    return Ok(Some(can));
+                }
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+fn walk(
+    visited: &mut HashSet<String>,
+    codes: &mut Vec<String>,
+    should_follow: &dyn Fn(&Room, &str) -> bool,
+    vm: Box<VM>,
+    room: Room,
+    progress: ProgressCallback,
+) -> Result<()> {
+    for exit in &room.exits {
+        if !should_follow(&room, exit) {
+            continue;
+        }
+
+        let mut vm = vm.clone();
+        let (prelude, next_room) = vm.send_command(exit)?;
+
+        let prelude = prelude.trim();
+
+        if prelude.is_empty() {
+            /* do nothing */
+        } else if prelude.starts_with("Chiseled") {
+            codes.push(prelude.to_owned());
+        } else {
+            bail!(eyre!("Unknown prelude: {:?}", prelude));
+        }
+
+        if let Some(next_room) = next_room {
+            if visited.insert(next_room.description.clone()) {
+                if let Some(progress) = progress {
+                    progress(Progress::Rooms(visited.len()));
+                }
+                walk(visited, codes, should_follow, vm, next_room, progress)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Starting anywhere in the twisty passages, find the can, fill the lantern
+/// with it and light it up, only taking exits `should_follow` allows, like
+/// [`in_passages`]. Returns the VM and the room it's left in.
+pub fn light_lantern(
+    mut vm: Box<VM>,
+    should_follow: impl Fn(&Room, &str) -> bool,
+) -> Result<(Box<VM>, Room)> {
+    vm.append_input("look\n")?;
+    let start = vm
+        .cycle_until_next_room()?
+        .1
+        .ok_or_else(|| eyre!("not in a room"))?;
+
+    // the start is as good as explored already
+    let mut visited = HashSet::new();
+    visited.insert(start.description.clone());
+    let mut vm =
+        find_can(&mut visited, &should_follow, vm, start)?.ok_or_else(|| eyre!("no can found"))?;
+
+    // skip taken message
+    vm.cycle_until_next_room()?;
+
+    // skip use can message
+    vm.cycle_until_next_room()?;
+
+    // use lantern, for whatever reason, prints the room
+    let start = vm
+        .cycle_until_next_room()?
+        .1
+        .ok_or_else(|| eyre!("no room after lighting the lantern"))?;
+
+    Ok((vm, start))
+}
+
+/// Walk the lit passages and collect every chiseled code found on the way,
+/// only taking exits `should_follow` allows.
+pub fn find_codes(
+    vm: Box<VM>,
+    start: Room,
+    should_follow: impl Fn(&Room, &str) -> bool,
+) -> Result<Vec<String>> {
+    map_passages(vm, start, &should_follow, None)
+}
+
+/// Like [`find_codes`], reporting how many rooms have been explored.
+pub fn find_codes_with_progress(
+    vm: Box<VM>,
+    start: Room,
+    should_follow: impl Fn(&Room, &str) -> bool,
+    progress: impl Fn(Progress) + Sync,
+) -> Result<Vec<String>> {
+    map_passages(vm, start, &should_follow, Some(&progress))
+}
+
+fn map_passages(
+    vm: Box<VM>,
+    start: Room,
+    should_follow: &dyn Fn(&Room, &str) -> bool,
+    progress: ProgressCallback,
+) -> Result<Vec<String>> {
+    // the start is as good as explored already
+    let mut visited = HashSet::new();
+    visited.insert(start.description.clone());
+    let mut codes = Vec::new();
+    walk(&mut visited, &mut codes, should_follow, vm, start, progress)?;
+    Ok(codes)
+}
@@ -0,0 +1,320 @@
+use array_iterator::ArrayIterator;
+use std::{collections::HashMap, fmt::Display, str::FromStr};
+
+use eyre::{bail, eyre, Report, Result, WrapErr};
+
+use super::{graph::bfs, Progress, ProgressCallback, VM};
+use crate::{Room, MAX_VALUE};
+
+const GRID_SIDE: i64 = 4;
+const TARGET_WEIGHT: i32 = 30;
+
+#[derive(Clone, Copy, Debug)]
+enum Cell {
+    Num(i32),
+    Add,
+    Mul,
+    Sub,
+}
+
+impl Display for Cell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Cell::Num(n) => n.fmt(f),
+            Cell::Add => f.pad("+"),
+            Cell::Mul => f.pad("*"),
+            Cell::Sub => f.pad("-"),
+        }
+    }
+}
+
+impl FromStr for Cell {
+    type Err = Report;
+
+    fn from_str(desc: &str) -> Result<Self> {
+        Ok(if desc.contains('+') {
+            Cell::Add
+        } else if desc.contains('-') {
+            Cell::Sub
+        } else if desc.contains('*') {
+            Cell::Mul
+        } else {
+            let start = desc.find('\'').ok_or_else(|| eyre!("weird desc"))?;
+            let end = (start + 1)
+                + desc[start + 1..]
+                    .find('\'')
+                    .ok_or_else(|| eyre!("weird desc"))?;
+            Cell::Num(desc[start + 1..end].parse()?)
+        })
+    }
+}
+
+/// Whether `exit` keeps to the grid of rooms in front of the vault, as
+/// opposed to going into the vault itself.
+pub fn in_grid(_room: &Room, exit: &str) -> bool {
+    exit != "vault"
+}
+
+/// Whether the orb made it through the move that printed `prelude`, as
+/// opposed to its weight going out of range and the orb shattering, which
+/// rules the move out.
+pub fn orb_survived(prelude: &str) -> bool {
+    !prelude.contains("The orb shatters!")
+}
+
+fn walk(
+    grid: &mut HashMap<(i64, i64), Cell>,
+    should_follow: &dyn Fn(&Room, &str) -> bool,
+    (x, y): (i64, i64),
+    vm: Box<VM>,
+    room: Room,
+    progress: ProgressCallback,
+) -> Result<()> {
+    // don't revisit visited squares
+    if grid.insert((x, y), room.description.parse()?).is_some() {
+        return Ok(());
+    }
+    if let Some(progress) = progress {
+        progress(Progress::Rooms(grid.len()));
+    }
+
+    // the orb disappears at the vault door
+    if room.title == "Vault Door" {
+        return Ok(());
+    }
+
+    for exit in &room.exits {
+        if !should_follow(&room, exit) {
+            continue;
+        }
+
+        // go into every exit
+        let mut vm = vm.clone();
+        let (prelude, next_room) = vm.send_command(exit)?;
+
+        // calculate the next position
+        let next_pos = match exit.as_ref() {
+            "east" => (x + 1, y),
+            "west" => (x - 1, y),
+            "north" => (x, y + 1),
+            "south" => (x, y - 1),
+            _ => bail!(eyre!("unexpected exit {:?} at {:?}", exit, (x, y))),
+        };
+
+        // if the orb shatters, we can't go in this direction
+        if !orb_survived(&prelude) {
+            continue;
+        }
+
+        if let Some(next_room) = next_room {
+            // avoid going outside the grid
+            if !next_room.title.starts_with("Vault") || next_room.title == "Vault Antechamber" {
+                continue;
+            }
+
+            // keep exploring from the next position
+            walk(grid, should_follow, next_pos, vm, next_room, progress)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Draw the grid with north at the top, bracketing the antechamber (where
+/// the orb starts) and the vault door, and showing rooms that weren't mapped
+/// as `?`.
+fn render_grid(grid: &HashMap<(i64, i64), Cell>) -> String {
+    let mut rendered = String::new();
+    for y in (0..GRID_SIDE).rev() {
+        for x in 0..GRID_SIDE {
+            let cell = grid
+                .get(&(x, y))
+                .map_or_else(|| "?".to_owned(), Cell::to_string);
+            let cell = if (x, y) == (0, 0) || (x, y) == (GRID_SIDE - 1, GRID_SIDE - 1) {
+                format!("[{}]", cell)
+            } else {
+                cell
+            };
+            rendered.push_str(&format!("{:^6}", cell));
+        }
+        rendered.truncate(rendered.trim_end().len());
+        rendered.push('\n');
+    }
+    rendered
+}
+
+fn pathfind(graph: &HashMap<(i64, i64), Cell>) -> Result<Vec<(i64, i64)>> {
+    // numbers and operators alternate, so the search can take it for granted
+    let is_operator = |cell: &Cell| !matches!(cell, Cell::Num(..));
+    for (&(x, y), cell) in graph {
+        for next in ArrayIterator::new([(x + 1, y), (x, y + 1)]) {
+            if is_operator(cell) && graph.get(&next).is_some_and(is_operator) {
+                bail!(eyre!(
+                    "two operators next to each other at {:?} and {:?}",
+                    (x, y),
+                    next
+                ));
+            }
+        }
+    }
+    if !graph.contains_key(&(0, 0)) {
+        bail!(eyre!("no cell at the antechamber"));
+    }
+
+    // nodes are a position along with the orb's weight there
+    let neighbors = |&(x, y, w): &(i64, i64, i32)| {
+        let cell = graph[&(x, y)];
+        let mut neighbors = Vec::new();
+
+        // the orb disappears in the throne room
+        if (x, y) == (GRID_SIDE - 1, GRID_SIDE - 1) {
+            return neighbors;
+        }
+
+        for (nx, ny) in ArrayIterator::new([(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)]) {
+            if (nx, ny) == (0, 0) {
+                // orb disappears in antechamber
+                continue;
+            }
+
+            let ncell = match graph.get(&(nx, ny)) {
+                Some(&ncell) => ncell,
+                None => continue,
+            };
+
+            let nw = match (cell, ncell) {
+                (Cell::Num(..), ..) => w,
+                (Cell::Add, Cell::Num(n)) => w + n,
+                (Cell::Mul, Cell::Num(n)) => w * n,
+                (Cell::Sub, Cell::Num(n)) => w - n,
+                _ => unreachable!("two operators next to each other"),
+            };
+            if (0..4).contains(&nx) && (0..4).contains(&ny) && (0..MAX_VALUE as i32).contains(&nw) {
+                neighbors.push((nx, ny, nw));
+            }
+        }
+
+        neighbors
+    };
+
+    let goal = (GRID_SIDE - 1, GRID_SIDE - 1, TARGET_WEIGHT);
+    let path = bfs((0, 0, 22), neighbors, |&node| node == goal).ok_or_else(|| {
+        eyre!(
+            "no path reaches the vault door with weight {}",
+            TARGET_WEIGHT
+        )
+    })?;
+    Ok(path.into_iter().map(|(x, y, _)| (x, y)).collect())
+}
+
+/// Starting from the vault antechamber, find the sequence of exits that
+/// carries the orb to the vault door with the right weight. Only exits
+/// `should_follow` allows, like [`in_grid`], get mapped.
+pub fn solve_weighted_grid(
+    vm: Box<VM>,
+    should_follow: impl Fn(&Room, &str) -> bool,
+) -> Result<Vec<&'static str>> {
+    solve(vm, &should_follow, None)
+}
+
+/// Like [`solve_weighted_grid`], reporting how many of the grid's rooms have
+/// been mapped.
+pub fn solve_weighted_grid_with_progress(
+    vm: Box<VM>,
+    should_follow: impl Fn(&Room, &str) -> bool,
+    progress: impl Fn(Progress) + Sync,
+) -> Result<Vec<&'static str>> {
+    solve(vm, &should_follow, Some(&progress))
+}
+
+fn solve(
+    mut vm: Box<VM>,
+    should_follow: &dyn Fn(&Room, &str) -> bool,
+    progress: ProgressCallback,
+) -> Result<Vec<&'static str>> {
+    vm.append_input("take orb\nlook\n")?;
+
+    vm.cycle_until_next_room()?;
+
+    let start = vm
+        .cycle_until_next_room()?
+        .1
+        .ok_or_else(|| eyre!("not in a room after taking the orb"))?;
+    let mut graph = HashMap::new();
+    walk(&mut graph, should_follow, (0, 0), vm, start, progress)?;
+    graph.insert((3, 3), Cell::Num(1));
+
+    let path =
+        pathfind(&graph).wrap_err_with(|| format!("in this grid:\n{}", render_grid(&graph)))?;
+
+    path.windows(2)
+        .map(|step| {
+            Ok(match (step[1].0 - step[0].0, step[1].1 - step[0].1) {
+                (1, 0) => "east",
+                (-1, 0) => "west",
+                (0, 1) => "north",
+                (0, -1) => "south",
+                delta => bail!(eyre!("non-adjacent step {:?} in path", delta)),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the challenge's grid
+    fn challenge_grid() -> HashMap<(i64, i64), Cell> {
+        // bottom row first
+        let rows = [
+            [Cell::Num(22), Cell::Sub, Cell::Num(9), Cell::Mul],
+            [Cell::Add, Cell::Num(4), Cell::Sub, Cell::Num(18)],
+            [Cell::Num(4), Cell::Mul, Cell::Num(11), Cell::Mul],
+            [Cell::Mul, Cell::Num(8), Cell::Sub, Cell::Num(1)],
+        ];
+        let mut graph = HashMap::new();
+        for (y, row) in rows.iter().enumerate() {
+            for (x, &cell) in row.iter().enumerate() {
+                graph.insert((x as i64, y as i64), cell);
+            }
+        }
+        graph
+    }
+
+    #[test]
+    fn test_orb_survived() {
+        assert!(orb_survived(
+            "south\n\nAs you enter the room, the symbol on the floor briefly flashes green.  \
+             The orb begins subtly glowing yellow.  The orb seems to get heavier.\n\n"
+        ));
+        assert!(!orb_survived(
+            "east\n\nAs you enter the room, the symbol on the floor briefly flashes red.  \
+             The orb shatters!\n\n"
+        ));
+    }
+
+    #[test]
+    fn test_render_grid() {
+        let mut graph = challenge_grid();
+        graph.remove(&(2, 1));
+        assert_eq!(
+            render_grid(&graph),
+            concat!(
+                "  *     8     -    [1]\n",
+                "  4     *     11    *\n",
+                "  +     4     ?     18\n",
+                " [22]   -     9     *\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_pathfind() {
+        let graph = challenge_grid();
+        let path = pathfind(&graph).unwrap();
+        assert_eq!(path.first(), Some(&(0, 0)));
+        assert_eq!(path.last(), Some(&(3, 3)));
+        assert_eq!(path.len(), 13);
+    }
+}
@@ -0,0 +1,37 @@
+use std::io::{self, Cursor, Write};
+
+/// An output that passes everything on to another one while keeping a copy,
+/// so that things like [`VM::cycle_until_next_room`](crate::VM::cycle_until_next_room)
+/// can look at what the program printed whatever the output really is.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Tee<W> {
+    pub inner: W,
+
+    /// Everything written so far.
+    pub copy: Cursor<Vec<u8>>,
+}
+
+impl<W: Write> Tee<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            copy: Cursor::new(Vec::new()),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for Tee<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.copy.write_all(&buf[..len])?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,78 @@
+use std::io::{self, BufWriter, Read, Write};
+
+use crate::{decode, VM};
+
+/// A machine-readable execution trace: one JSON object per line for every
+/// instruction run, like
+///
+/// ```text
+/// {"pc":1234,"op":"add","args":[32768,32769,1],"regs":[0,0,0,0,0,0,0,0],"sp":0}
+/// ```
+///
+/// `regs` and `sp` (the stack depth) are as they were before the instruction
+/// ran. Traces get big fast, so writes are buffered.
+#[derive(Debug)]
+pub struct Trace<W: Write> {
+    out: BufWriter<W>,
+}
+
+impl<W: Write> Trace<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out: BufWriter::new(out),
+        }
+    }
+
+    /// Write out the instruction `vm` is about to run.
+    pub fn record<I: Read, O: Write>(&mut self, vm: &VM<I, O>) -> io::Result<()> {
+        write!(self.out, "{{\"pc\":{},", vm.pc)?;
+        match decode(&vm.memory, vm.pc) {
+            Ok(instruction) => write!(
+                self.out,
+                "\"op\":\"{}\",\"args\":{:?},",
+                instruction.opcode.name(),
+                instruction.args()
+            )?,
+            // running it will fail, but it's still worth a line
+            Err(_) => write!(self.out, "\"op\":null,\"args\":[],")?,
+        }
+        writeln!(
+            self.out,
+            "\"regs\":{:?},\"sp\":{}}}",
+            vm.registers,
+            vm.stack.len()
+        )
+    }
+
+    /// Flush what's still buffered and hand back the underlying writer.
+    pub fn into_inner(self) -> io::Result<W> {
+        self.out
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace() {
+        let program: &[u8] = &[
+            1, 0, 0, 128, 1, 0, // set r0 1
+            0, 0, // halt
+        ];
+        let mut vm = VM::load_program(io::empty(), io::sink(), program);
+        let mut trace = Trace::new(Vec::new());
+        vm.run_to_halt_with(|vm| Ok(trace.record(vm)?)).unwrap();
+
+        let trace = String::from_utf8(trace.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            trace,
+            concat!(
+                "{\"pc\":0,\"op\":\"set\",\"args\":[32768, 1],\"regs\":[0, 0, 0, 0, 0, 0, 0, 0],\"sp\":0}\n",
+                "{\"pc\":3,\"op\":\"halt\",\"args\":[],\"regs\":[1, 0, 0, 0, 0, 0, 0, 0],\"sp\":0}\n",
+            )
+        );
+    }
+}
@@ -1,15 +1,69 @@
-use std::{
+use core::{
     convert::{TryFrom, TryInto},
+    fmt,
+    mem::MaybeUninit,
+    ops::Range,
+};
+
+use alloc::collections::{BTreeSet, VecDeque};
+#[cfg(not(feature = "std"))]
+use alloc::{boxed::Box, vec, vec::Vec};
+#[cfg(feature = "std")]
+use std::{
+    collections::HashMap,
     io::{self, Read, Write},
-    mem::size_of,
+    sync::{Arc, Mutex},
 };
 
-use eyre::{bail, Result};
+#[cfg(not(feature = "std"))]
+use crate::io::{Read, Write};
+use crate::{decode, Instruction, Opcode, Register};
+#[cfg(feature = "std")]
+use crate::{MemoryHook, MemoryHooks};
+#[cfg(feature = "std")]
+use eyre::{bail, eyre, Result};
+
+#[cfg(not(feature = "std"))]
+type Result<T> = core::result::Result<T, Error>;
+
+#[cfg(not(feature = "std"))]
+macro_rules! bail {
+    ($err:expr) => {
+        return Err($err.into())
+    };
+}
+
+/// The words of a little-endian program image.
+pub(crate) fn program_words(program: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    program
+        .chunks_exact(2)
+        .map(|chunk| u32::from(u16::from_le_bytes(chunk.try_into().unwrap())))
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn vm_error(err: &eyre::Report) -> Option<&Error> {
+    err.downcast_ref()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn vm_error(err: &Error) -> Option<&Error> {
+    Some(err)
+}
+
+/// How many bits a value has.
+pub const INTEGER_SIZE: usize = 15;
+
+/// One past the largest value, which is also what arithmetic wraps around at
+/// and where the register operands start.
+pub const MAX_VALUE: u32 = 1 << INTEGER_SIZE;
+
+/// How many words of memory there are.
+pub const ADDRESS_SPACE: usize = MAX_VALUE as usize;
 
-const INTEGER_SIZE: usize = 15;
-const MAX_VALUE: u32 = 1 << INTEGER_SIZE;
-const ADDRESS_SPACE: usize = MAX_VALUE as usize;
-const REGISTER_COUNT: usize = 8;
+pub const REGISTER_COUNT: usize = 8;
+
+// how much buffered output `out` holds on to before writing it out anyway
+const OUT_BUFFER_LEN: usize = 8 * 1024;
 
 pub const INSTRUCTION_NAMES_AND_ARGS: [(&str, usize); 22] = [
     ("halt", 0),
@@ -37,19 +91,19 @@ pub const INSTRUCTION_NAMES_AND_ARGS: [(&str, usize); 22] = [
 ];
 pub struct DisplayArgument(pub u32);
 
-impl std::fmt::Display for DisplayArgument {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if self.0 <= 32767 {
+impl core::fmt::Display for DisplayArgument {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.0 < MAX_VALUE {
             write!(f, "{}", self.0)
-        } else if self.0 <= 32775 {
-            write!(f, "r{}", self.0 - 32768)
+        } else if let Ok(register) = Register::try_from(self.0) {
+            write!(f, "{}", register)
         } else {
-            Err(std::fmt::Error)
+            Err(core::fmt::Error)
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq)]
 pub struct VM<Input: Read, Output: Write> {
     pub memory: [u32; ADDRESS_SPACE],
 
@@ -61,6 +115,80 @@ pub struct VM<Input: Read, Output: Write> {
 
     pub input: Input,
     pub output: Output,
+
+    #[cfg(feature = "std")]
+    pub hooks: MemoryHooks,
+
+    // the last room `cycle_until_next_room` came across
+    #[cfg(feature = "std")]
+    pub(crate) current_room: Option<crate::Room>,
+
+    pub input_eof: InputEof,
+
+    // how many bytes `in` has read since the last newline
+    partial_line_len: usize,
+
+    pub input_newline: InputNewlineMode,
+
+    pub flush_policy: FlushPolicy,
+
+    /// Fail on things the spec rules out instead of muddling through, like
+    /// printing a character past 255.
+    pub strict: bool,
+
+    /// Make `push` and `call` fail with [`Error::StackOverflow`] rather than
+    /// grow the stack past this many entries.
+    pub max_stack_depth: Option<usize>,
+
+    /// Make [`VM::run_for`] stop once the stack grows to this many entries.
+    pub stack_watchpoint: Option<usize>,
+
+    /// Make [`VM::run_for`] stop with [`StopReason::NoProgress`] when the
+    /// program gets back to the same pc, registers and stack depth without
+    /// having printed, read or written memory in between. Only this many
+    /// states are remembered at once, which bounds the memory it takes.
+    pub no_progress_window: Option<usize>,
+
+    // addresses where `run_for` stops before running the instruction
+    breakpoints: BTreeSet<usize>,
+
+    // the addresses of the last `history_len` instructions run, oldest first
+    history: VecDeque<usize>,
+    history_len: usize,
+
+    // address ranges that `wmem` refuses to write to
+    protected: Vec<Range<usize>>,
+
+    /// How many instructions have been executed so far.
+    pub instruction_count: u64,
+
+    // how many times each opcode has run, if counting is enabled
+    opcode_histogram: Option<[u64; INSTRUCTION_NAMES_AND_ARGS.len()]>,
+
+    // instructions decoded so far, by address, if caching is enabled
+    decode_cache: Option<Vec<Option<Instruction>>>,
+
+    // what `out` has printed but not yet written, if buffering is enabled
+    out_buffer: Option<Vec<u8>>,
+}
+
+// the memory is too big to be worth printing, so only say how much is in use
+impl<Input: Read, Output: Write> fmt::Debug for VM<Input, Output> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let memory_len = self
+            .memory
+            .iter()
+            .rposition(|&word| word != 0)
+            .map_or(0, |last| last + 1);
+
+        f.debug_struct("VM")
+            .field("pc", &self.pc)
+            .field("registers", &self.registers)
+            .field("stack", &self.stack)
+            .field("instruction_count", &self.instruction_count)
+            .field("memory", &format_args!("[{} words in use]", memory_len))
+            .finish_non_exhaustive()
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -79,93 +207,541 @@ pub enum Error {
 
     #[error("Program halted")]
     Halt,
+
+    #[error("Returned from an empty stack")]
+    ReturnFromEmptyStack,
+
+    #[error("Ran out of input")]
+    InputExhausted,
+
+    #[error("Ran out of input {0} bytes into a line, is it missing a newline?")]
+    PartialInput(usize),
+
+    #[error("Instruction at {0:#x} runs past the end of memory")]
+    TruncatedInstruction(usize),
+
+    #[error("Stack grew past {0} entries")]
+    StackOverflow(usize),
+
+    #[error("Tried to output invalid character {0:#x}")]
+    InvalidOutputChar(u32),
+
+    #[error("Tried to write to protected address {0:#x}")]
+    WriteToProtectedMemory(usize),
+
+    #[error("Tried to jump to {0:#x}, past the end of memory")]
+    InvalidJump(usize),
+
+    #[error("Program is {0} bytes long, which isn't a whole number of words")]
+    MalformedProgram(usize),
+
+    #[error("Program is {0} words long, which doesn't fit in memory")]
+    ProgramTooLarge(usize),
+
+    #[error("Snapshot is truncated or corrupted")]
+    CorruptSnapshot,
+
+    #[error("Unsupported snapshot version {0}")]
+    UnsupportedSnapshotVersion(u32),
+
+    #[cfg(not(feature = "std"))]
+    #[error("I/O error")]
+    Io(#[from] crate::io::Error),
+}
+
+impl Error {
+    /// Whether this just means the program is over: both `halt` and a `ret`
+    /// with nothing to return to end it.
+    pub fn is_halt(&self) -> bool {
+        matches!(self, Error::Halt | Error::ReturnFromEmptyStack)
+    }
+}
+
+/// What the `in` opcode does when there's no more input to read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputEof {
+    /// Halt, just like the `halt` opcode would.
+    #[default]
+    Halt,
+
+    /// Fail with [`Error::InputExhausted`]. The VM is left ready to retry the
+    /// `in` once more input is available.
+    Error,
+
+    /// Keep retrying the read until some input shows up.
+    Block,
+
+    /// Read this byte instead.
+    Sentinel(u8),
+
+    /// Halt if the input ran out between lines, but fail with
+    /// [`Error::PartialInput`] if it ran out partway through one, which is
+    /// usually a script missing its last newline.
+    Strict,
+}
+
+/// When the `out` opcode flushes the output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Leave it up to the output, which is fastest for bulk runs.
+    #[default]
+    Never,
+
+    /// After every character.
+    EveryChar,
+
+    /// After every newline, so that an interactive user sees whole lines as
+    /// soon as they're done.
+    EveryLine,
+}
+
+/// What the `in` opcode does with carriage returns.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputNewlineMode {
+    /// Skip them, so that input with Windows line endings reads the same as
+    /// input without.
+    #[default]
+    SkipCr,
+
+    /// Hand every byte over as is.
+    Raw,
+}
+
+/// Why a call to [`VM::run_for`] returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// The program halted.
+    Halted,
+
+    /// The cycle cap was reached before the program halted.
+    CycleLimit,
+
+    /// Execution reached the breakpoint at this address.
+    Breakpoint(usize),
+
+    /// The stack grew to [`VM::stack_watchpoint`] entries.
+    StackDepth(usize),
+
+    /// The program is waiting for input. Only [`VM::run_capped`] reports
+    /// this, whatever [`VM::input_eof`] is; [`VM::run_for`] halts or fails
+    /// with [`Error::InputExhausted`] instead.
+    NeedsInput,
+
+    /// [`VM::step_over`] or [`VM::step_out`] got where it was going.
+    Stepped,
+
+    /// The program came back to the same state at this address without
+    /// getting anything done, so it's stuck. See [`VM::no_progress_window`].
+    NoProgress(usize),
+
+    /// [`VM::run_until_output_contains`] found what it was looking for.
+    OutputMatched,
 }
 
 impl<Input: Read, Output: Write> VM<Input, Output> {
-    pub fn load_program(input: Input, output: Output, program: &'static [u8]) -> Box<Self> {
-        let mut this = Box::new(Self {
-            memory: [0; ADDRESS_SPACE],
-            registers: [0; REGISTER_COUNT],
-            stack: Vec::new(),
-            pc: 0,
-            input,
-            output,
-        });
-
-        program
-            .chunks_exact(2)
-            .zip(this.memory.iter_mut())
-            .for_each(|(chunk, cell)| {
-                *cell = u32::from(u16::from_le_bytes(chunk.try_into().unwrap()));
-            });
+    /// Build a VM whose memory starts with `words`, zeroing whatever they
+    /// don't cover. The memory is written straight into the heap
+    /// allocation, so it never gets zeroed only to be overwritten, nor
+    /// copied over from the stack.
+    pub(crate) fn from_words(
+        input: Input,
+        output: Output,
+        words: impl IntoIterator<Item = u32>,
+    ) -> Box<Self> {
+        Self::from_words_at(input, output, 0, words)
+    }
+
+    /// Like [`VM::from_words`], but with the words starting at `offset`,
+    /// which is also where the pc starts.
+    fn from_words_at(
+        input: Input,
+        output: Output,
+        offset: usize,
+        words: impl IntoIterator<Item = u32>,
+    ) -> Box<Self> {
+        let mut this = Box::<Self>::new_uninit();
+        let ptr = this.as_mut_ptr();
+
+        // SAFETY: `ptr` points to a live allocation for `Self`, and
+        // `MaybeUninit<u32>` has the same layout as `u32`
+        let memory =
+            unsafe { &mut *(&raw mut (*ptr).memory).cast::<[MaybeUninit<u32>; ADDRESS_SPACE]>() };
+        memory[..offset].fill(MaybeUninit::new(0));
+        let mut len = offset;
+        for (cell, word) in memory[offset..].iter_mut().zip(words) {
+            cell.write(word);
+            len += 1;
+        }
+        memory[len..].fill(MaybeUninit::new(0));
 
+        // SAFETY: the memory was initialized above
+        let mut this = unsafe {
+            Self::init_fields(ptr, input, output);
+            this.assume_init()
+        };
+        this.pc = offset;
         this
     }
 
-    pub fn save_snapshot(&self, mut w: impl io::Write) -> Result<()> {
-        // memory: [u32; ADDRESS_SPACE]
-        w.write_all(bytemuck::cast_slice(&self.memory))?;
+    /// Build a VM with all-zero memory, for callers that are about to
+    /// overwrite all of it anyway. The allocator can often hand out zeroed
+    /// memory for free, which an explicit fill can't take advantage of.
+    #[cfg(feature = "std")]
+    pub(crate) fn zeroed(input: Input, output: Output) -> Box<Self> {
+        let mut this = Box::<Self>::new_zeroed();
+
+        // SAFETY: all-zero bytes are a valid memory
+        unsafe {
+            Self::init_fields(this.as_mut_ptr(), input, output);
+            this.assume_init()
+        }
+    }
+
+    /// Initialize every field but `memory`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to an allocation for `Self`.
+    unsafe fn init_fields(ptr: *mut Self, input: Input, output: Output) {
+        // if a field gets added, it needs to be initialized below too
+        let _ = |this: Self| {
+            let Self {
+                memory: _,
+                registers: _,
+                stack: _,
+                pc: _,
+                input: _,
+                output: _,
+                #[cfg(feature = "std")]
+                    hooks: _,
+                #[cfg(feature = "std")]
+                    current_room: _,
+                input_eof: _,
+                partial_line_len: _,
+                input_newline: _,
+                flush_policy: _,
+                strict: _,
+                max_stack_depth: _,
+                stack_watchpoint: _,
+                no_progress_window: _,
+                breakpoints: _,
+                history: _,
+                history_len: _,
+                protected: _,
+                instruction_count: _,
+                opcode_histogram: _,
+                decode_cache: _,
+                out_buffer: _,
+            } = this;
+        };
 
-        // registers: [u32; REGISTER_COUNT]
-        w.write_all(bytemuck::cast_slice(&self.registers))?;
+        unsafe {
+            (&raw mut (*ptr).registers).write([0; REGISTER_COUNT]);
+            (&raw mut (*ptr).stack).write(Vec::new());
+            (&raw mut (*ptr).pc).write(0);
+            (&raw mut (*ptr).input).write(input);
+            (&raw mut (*ptr).output).write(output);
+            #[cfg(feature = "std")]
+            (&raw mut (*ptr).hooks).write(MemoryHooks::default());
+            #[cfg(feature = "std")]
+            (&raw mut (*ptr).current_room).write(None);
+            (&raw mut (*ptr).input_eof).write(InputEof::default());
+            (&raw mut (*ptr).partial_line_len).write(0);
+            (&raw mut (*ptr).input_newline).write(InputNewlineMode::default());
+            (&raw mut (*ptr).flush_policy).write(FlushPolicy::default());
+            (&raw mut (*ptr).strict).write(false);
+            (&raw mut (*ptr).max_stack_depth).write(None);
+            (&raw mut (*ptr).stack_watchpoint).write(None);
+            (&raw mut (*ptr).no_progress_window).write(None);
+            (&raw mut (*ptr).breakpoints).write(BTreeSet::new());
+            (&raw mut (*ptr).history).write(VecDeque::new());
+            (&raw mut (*ptr).history_len).write(0);
+            (&raw mut (*ptr).protected).write(Vec::new());
+            (&raw mut (*ptr).instruction_count).write(0);
+            (&raw mut (*ptr).opcode_histogram).write(None);
+            (&raw mut (*ptr).decode_cache).write(None);
+            (&raw mut (*ptr).out_buffer).write(None);
+        }
+    }
+
+    pub fn load_program(input: Input, output: Output, program: &'static [u8]) -> Box<Self> {
+        Self::from_words(input, output, program_words(program))
+    }
 
-        // pc: usize,
-        w.write_all(&self.pc.to_ne_bytes())?;
+    /// Load `program` starting at `offset` rather than at 0, and start running
+    /// it there. Whatever doesn't fit in memory is cut off.
+    ///
+    /// # Panics
+    ///
+    /// If `offset` is past the end of memory.
+    pub fn load_program_at(
+        input: Input,
+        output: Output,
+        program: &[u8],
+        offset: usize,
+    ) -> Box<Self> {
+        Self::from_words_at(input, output, offset, program_words(program))
+    }
 
-        // stack: Stack<u32>
-        w.write_all(bytemuck::cast_slice(&self.stack))?;
+    /// Swap in a new program and start it from the top, keeping the same
+    /// input, output, breakpoints and hooks. The registers, stack and pc are
+    /// reset; to keep the registers, copy them out first and put them back.
+    pub fn reload_program(&mut self, program: &[u8]) -> Result<()> {
+        if !program.len().is_multiple_of(2) {
+            bail!(Error::MalformedProgram(program.len()));
+        }
+        if program.len() / 2 > ADDRESS_SPACE {
+            bail!(Error::ProgramTooLarge(program.len() / 2));
+        }
 
+        self.memory.fill(0);
+        for (cell, word) in self.memory.iter_mut().zip(program_words(program)) {
+            *cell = word;
+        }
+        self.registers = [0; REGISTER_COUNT];
+        self.stack.clear();
+        self.pc = 0;
+        self.history.clear();
+        self.instruction_count = 0;
+        self.partial_line_len = 0;
+        #[cfg(feature = "std")]
+        {
+            self.current_room = None;
+        }
+        self.invalidate_decode_cache();
         Ok(())
     }
 
-    pub fn load_snapshot(input: Input, output: Output, r: impl io::Read) -> Result<Box<Self>> {
-        let mut this = Box::new(Self {
-            memory: [0; ADDRESS_SPACE],
-            registers: [0; REGISTER_COUNT],
-            stack: Vec::new(),
-            pc: 0,
-            input,
-            output,
-        });
-        this.load_snapshot_inplace(r)?;
-        Ok(this)
+    /// Start caching decoded instructions, so that code that runs over and
+    /// over only gets decoded once. `wmem` keeps the cache up to date, but
+    /// anything else that changes `memory` directly must call
+    /// [`VM::invalidate_decode_cache`] afterwards.
+    pub fn enable_decode_cache(&mut self) {
+        self.decode_cache = Some(vec![None; ADDRESS_SPACE]);
     }
 
-    pub fn load_snapshot_inplace(&mut self, mut r: impl io::Read) -> Result<()> {
-        // memory: [u32; ADDRESS_SPACE]
-        r.read_exact(bytemuck::cast_slice_mut(&mut self.memory))?;
+    pub fn disable_decode_cache(&mut self) {
+        self.decode_cache = None;
+    }
 
-        // registers: [u32; REGISTER_COUNT]
-        r.read_exact(bytemuck::cast_slice_mut(&mut self.registers))?;
+    /// Forget every cached instruction, e.g. after patching `memory`.
+    pub fn invalidate_decode_cache(&mut self) {
+        if let Some(cache) = &mut self.decode_cache {
+            cache.iter_mut().for_each(|instruction| *instruction = None);
+        }
+    }
 
-        // pc: usize,
-        let mut pc_bytes = [0; size_of::<usize>()];
-        r.read_exact(&mut pc_bytes)?;
-        self.pc = usize::from_ne_bytes(pc_bytes);
+    /// Have `out` collect characters and write them in bulk, which saves a
+    /// syscall per character on outputs that don't buffer, like a pipe or a
+    /// file. They're written on [`VM::flush`], before `in` reads, whenever
+    /// the [`FlushPolicy`] would flush, once the program halts or fails and
+    /// once [`VM::run_for`] returns.
+    pub fn enable_output_buffering(&mut self) {
+        self.out_buffer.get_or_insert_with(Vec::new);
+    }
 
-        // stack: Stack<u32>
-        let mut tos_bytes = [0; size_of::<u32>()];
-        while let Ok(()) = r.read_exact(&mut tos_bytes) {
-            self.stack.push(u32::from_ne_bytes(tos_bytes));
+    /// Stop buffering, writing out whatever was buffered so far.
+    pub fn disable_output_buffering(&mut self) -> Result<()> {
+        self.write_out_buffer()?;
+        self.out_buffer = None;
+        Ok(())
+    }
+
+    // write out what `out` has buffered, if anything
+    fn write_out_buffer(&mut self) -> Result<()> {
+        if let Some(buffer) = &mut self.out_buffer {
+            if !buffer.is_empty() {
+                self.output.write_all(buffer)?;
+                buffer.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Every address where `needle` appears in memory.
+    pub fn find_bytes(&self, needle: &[u32]) -> Vec<usize> {
+        if needle.is_empty() {
+            return Vec::new();
         }
 
+        self.memory
+            .windows(needle.len())
+            .enumerate()
+            .filter(|(_, window)| *window == needle)
+            .map(|(address, _)| address)
+            .collect()
+    }
+
+    /// Every address where `needle` appears in memory as one character per
+    /// word, looking only at each word's low byte.
+    pub fn find_str(&self, needle: &str) -> Vec<usize> {
+        if needle.is_empty() {
+            return Vec::new();
+        }
+
+        self.memory
+            .windows(needle.len())
+            .enumerate()
+            .filter(|(_, window)| {
+                window
+                    .iter()
+                    .zip(needle.bytes())
+                    .all(|(&word, ch)| word as u8 == ch)
+            })
+            .map(|(address, _)| address)
+            .collect()
+    }
+
+    /// Make [`VM::run_for`] stop when it's about to run the instruction at
+    /// `address`.
+    pub fn add_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address);
+    }
+
+    /// Returns whether there was a breakpoint at `address`.
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Start remembering the addresses of the last `len` instructions run.
+    pub fn enable_history(&mut self, len: usize) {
+        self.history_len = len;
+        while self.history.len() > len {
+            self.history.pop_front();
+        }
+    }
+
+    pub fn disable_history(&mut self) {
+        self.enable_history(0);
+    }
+
+    /// The addresses of the most recently run instructions, oldest first.
+    pub fn history(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.history.iter().copied()
+    }
+
+    /// How many bytes `in` has read since the last newline. Non-zero once
+    /// the VM halts means the input stopped partway through a line.
+    pub fn partial_line_len(&self) -> usize {
+        self.partial_line_len
+    }
+
+    /// Start counting how many times each opcode runs, from zero.
+    pub fn enable_opcode_histogram(&mut self) {
+        self.opcode_histogram = Some([0; INSTRUCTION_NAMES_AND_ARGS.len()]);
+    }
+
+    pub fn disable_opcode_histogram(&mut self) {
+        self.opcode_histogram = None;
+    }
+
+    /// How many times each opcode has run since
+    /// [`VM::enable_opcode_histogram`], leaving out the ones that never did.
+    #[cfg(feature = "std")]
+    pub fn opcode_histogram(&self) -> HashMap<Opcode, u64> {
+        self.opcode_histogram
+            .iter()
+            .flat_map(|histogram| Opcode::ALL.iter().copied().zip(histogram.iter().copied()))
+            .filter(|&(_, count)| count > 0)
+            .collect()
+    }
+
+    /// Make `wmem` fail with [`Error::WriteToProtectedMemory`] for the
+    /// addresses in `range`.
+    pub fn protect(&mut self, range: Range<usize>) {
+        self.protected.push(range);
+    }
+
+    /// Let `wmem` write to the addresses in `range` again.
+    pub fn unprotect(&mut self, range: Range<usize>) {
+        self.protected = self
+            .protected
+            .drain(..)
+            .flat_map(|protected| {
+                // whatever's left on either side of `range`
+                [
+                    protected.start..protected.end.min(range.start),
+                    protected.start.max(range.end)..protected.end,
+                ]
+            })
+            .filter(|piece| !piece.is_empty())
+            .collect();
+    }
+
+    /// Attach `hook` to the addresses in `range`. The caller can keep its own
+    /// handle to the hook to inspect it later.
+    #[cfg(feature = "std")]
+    pub fn add_memory_hook<H: MemoryHook + Send + 'static>(
+        &mut self,
+        range: Range<usize>,
+        hook: Arc<Mutex<H>>,
+    ) {
+        self.hooks.add(range, hook);
+    }
+
+    pub fn register(&self, register: Register) -> u32 {
+        self.registers[register.index()]
+    }
+
+    pub fn set_register(&mut self, register: Register, value: u32) {
+        self.registers[register.index()] = value;
+    }
+
+    /// A cheap fingerprint of the registers, the stack and the pc, e.g. to
+    /// check that a scripted run still ends up where it used to. It's the
+    /// same from run to run, but different states can share one.
+    pub fn state_hash(&self) -> u64 {
+        // FNV-1a, as std's hashers aren't available without std
+        let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+        let words = self
+            .registers
+            .iter()
+            .copied()
+            .chain([self.stack.len() as u32, self.pc as u32])
+            .chain(self.stack.iter().copied());
+        for word in words {
+            for byte in word.to_le_bytes() {
+                hash ^= u64::from(byte);
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+        hash
+    }
+
+    /// Push out anything `out` or the output have buffered up.
+    pub fn flush(&mut self) -> Result<()> {
+        self.write_out_buffer()?;
+        self.output.flush()?;
         Ok(())
     }
 
-    fn next_argument(&mut self) -> u32 {
-        let value = self.memory[self.pc];
-        self.pc += 1;
-        value
+    /// Move pc to `address`, which has to be in memory. In [`VM::strict`]
+    /// mode it also has to hold an instruction that decodes, which catches
+    /// a lot of jumps into data or into the middle of an instruction.
+    pub fn set_pc(&mut self, address: usize) -> Result<()> {
+        if address >= ADDRESS_SPACE {
+            bail!(Error::InvalidJump(address));
+        }
+        if self.strict {
+            decode(&self.memory, address)?;
+        }
+
+        self.pc = address;
+        Ok(())
     }
 
     pub fn load(&self, address: u32) -> Result<u32> {
         // - numbers 0..32767 mean a literal value
         // - numbers 32768..32775 instead mean registers 0..7
         // - numbers 32776..65535 are invalid
-        if address <= 32767 {
+        if address < MAX_VALUE {
             Ok(address)
-        } else if address <= 32775 {
-            Ok(self.registers[(address - 32768) as usize])
+        } else if let Ok(register) = Register::try_from(address) {
+            Ok(self.registers[register.index()])
         } else {
             bail!(Error::InvalidLoad(address))
         }
@@ -174,282 +750,625 @@ impl<Input: Read, Output: Write> VM<Input, Output> {
     fn set(&mut self, dest: u32, src: u32) -> Result<()> {
         let source = self.load(src)?;
 
-        let destination = if (32768..=32775).contains(&dest) {
-            &mut self.registers[(dest - 32768) as usize]
-        } else {
-            bail!(Error::InvalidStore(dest));
+        let register = match Register::try_from(dest) {
+            Ok(register) => register,
+            Err(_) => bail!(Error::InvalidStore(dest)),
         };
 
-        *destination = source;
+        self.registers[register.index()] = source;
         Ok(())
     }
 
     pub fn cycle(&mut self) -> Result<()> {
         let prev_pc = self.pc;
+        // the instruction could overwrite itself, so look at it beforehand
+        let opcode = self.memory[prev_pc];
+        #[cfg(feature = "log")]
+        self.log_instruction();
+
         match self.do_cycle() {
-            Ok(()) => Ok(()),
-            err @ Err(..) => {
+            Ok(()) => {
+                self.instruction_count += 1;
+                if let Some(histogram) = &mut self.opcode_histogram {
+                    histogram[opcode as usize] += 1;
+                }
+                if self.history_len > 0 {
+                    if self.history.len() == self.history_len {
+                        self.history.pop_front();
+                    }
+                    self.history.push_back(prev_pc);
+                }
+                Ok(())
+            }
+            Err(err) => {
                 self.pc = prev_pc;
-                err
+                #[cfg(feature = "log")]
+                if !vm_error(&err).is_some_and(Error::is_halt) {
+                    log::warn!("{:#06x}: {}", prev_pc, err);
+                }
+                // whatever the program printed before stopping should be seen
+                self.write_out_buffer()?;
+                Err(err)
             }
         }
     }
 
-    fn do_cycle(&mut self) -> Result<()> {
-        macro_rules! jmp {
-            ($location:expr) => {
-                self.pc = self.load($location)? as usize;
-            };
+    /// Log the instruction about to run, with its operands' values.
+    #[cfg(feature = "log")]
+    fn log_instruction(&self) {
+        if !log::log_enabled!(log::Level::Trace) {
+            return;
         }
 
-        macro_rules! bool_operation {
-            ($a:ident = $b:ident $op:tt $c:ident) => {
-                if self.load($b)? $op self.load($c)? {
-                    self.set($a, 1)?;
-                } else {
-                    self.set($a, 0)?;
-                }
+        if let Ok(instruction) = decode(&self.memory, self.pc) {
+            let mut values = [0; 3];
+            for (value, &arg) in values.iter_mut().zip(instruction.args()) {
+                *value = self.load(arg).unwrap_or(arg);
             }
+
+            log::trace!(
+                "{:#06x}: {} {:?}",
+                self.pc,
+                instruction,
+                &values[..instruction.args().len()]
+            );
         }
+    }
+
+    /// Run until the program halts.
+    pub fn run_to_halt(&mut self) -> Result<()> {
+        self.run_to_halt_with(|_| Ok(()))
+    }
+
+    /// Run until the program halts, calling `before` ahead of every
+    /// instruction, e.g. to record a trace. An error from it stops the run.
+    pub fn run_to_halt_with(&mut self, mut before: impl FnMut(&Self) -> Result<()>) -> Result<()> {
+        loop {
+            before(self)?;
+            match self.cycle() {
+                Ok(()) => {}
+                Err(err) => {
+                    if vm_error(&err).is_some_and(Error::is_halt) {
+                        return Ok(());
+                    }
 
-        macro_rules! binary_operation {
-            ($a:ident = $b:ident $op:tt $c:ident) => {
-                let b = self.load($b)?;
-                let c = self.load($c)?;
-                let result = (b $op c) % MAX_VALUE;
-                self.set($a, result)?;
+                    bail!(err);
+                }
             }
         }
+    }
 
-        let opcode = self.memory[self.pc];
-        self.pc += 1;
+    /// Run for at most `max_cycles` cycles, stopping early if the program
+    /// halts or reaches a breakpoint.
+    pub fn run_for(&mut self, max_cycles: u64) -> Result<StopReason> {
+        let reason = self.run_for_buffered(max_cycles)?;
+        self.write_out_buffer()?;
+        Ok(reason)
+    }
 
-        match opcode {
-            // halt: 0
-            //   stop execution and terminate the program
-            0 => bail!(Error::Halt),
-
-            // set: 1 a b
-            //   set register <a> to the value of <b>
-            1 => {
-                let a = self.next_argument();
-                let b = self.next_argument();
-                self.set(a, b)?;
-            }
+    // `run_for`, leaving whatever `out` buffered up in the buffer
+    fn run_for_buffered(&mut self, max_cycles: u64) -> Result<StopReason> {
+        // the states seen since the program last did something observable
+        let mut seen = BTreeSet::new();
 
-            // push: 2 a
-            //   push <a> onto the stack
-            2 => {
-                let a = self.next_argument();
-                let a_value = self.load(a)?;
-                self.stack.push(a_value);
+        for cycle in 0..max_cycles {
+            // a breakpoint we're already sitting on shouldn't stop us again
+            if cycle > 0 && self.breakpoints.contains(&self.pc) {
+                return Ok(StopReason::Breakpoint(self.pc));
             }
 
-            // pop: 3 a
-            //   remove the top element from the stack and write it into <a>; empty stack = error
-            3 => {
-                let a = self.next_argument();
-
-                if let Some(tos) = self.stack.pop() {
-                    self.set(a, tos)?;
+            if let Some(window) = self.no_progress_window {
+                let opcode = self.memory.get(self.pc).copied().and_then(Opcode::from_u32);
+                if matches!(opcode, Some(Opcode::Out | Opcode::In | Opcode::Wmem)) {
+                    seen.clear();
                 } else {
-                    bail!(Error::PopFromEmptyStack);
+                    if seen.len() >= window {
+                        seen.clear();
+                    }
+                    if !seen.insert((self.pc, self.registers, self.stack.len())) {
+                        return Ok(StopReason::NoProgress(self.pc));
+                    }
                 }
             }
 
-            // eq: 4 a b c
-            //   set <a> to 1 if <b> is equal to <c>; set it to 0 otherwise
-            4 => {
-                let a = self.next_argument();
-                let b = self.next_argument();
-                let c = self.next_argument();
+            let depth = self.stack.len();
 
-                bool_operation!(a = b == c);
-            }
-
-            // gt: 5 a b c
-            //   set <a> to 1 if <b> is greater than <c>; set it to 0 otherwise
-            5 => {
-                let a = self.next_argument();
-                let b = self.next_argument();
-                let c = self.next_argument();
+            match self.cycle() {
+                Ok(()) => {}
+                Err(err) => {
+                    if vm_error(&err).is_some_and(Error::is_halt) {
+                        return Ok(StopReason::Halted);
+                    }
 
-                bool_operation!(a = b > c);
+                    bail!(err);
+                }
             }
 
-            // jmp: 6 a
-            //   jump to <a>
-            6 => {
-                let a = self.next_argument();
-                jmp!(a);
+            if let Some(watchpoint) = self.stack_watchpoint {
+                if depth < watchpoint && self.stack.len() >= watchpoint {
+                    return Ok(StopReason::StackDepth(self.stack.len()));
+                }
             }
+        }
 
-            // jt: 7 a b
-            //   if <a> is nonzero, jump to <b>
-            7 => {
-                let a = self.next_argument();
-                let b = self.next_argument();
+        Ok(StopReason::CycleLimit)
+    }
 
-                if self.load(a)? != 0 {
-                    jmp!(b);
-                }
+    /// Run the instruction at pc, and if it's a `call` keep going until the
+    /// call returns. Breakpoints inside the call still stop it early.
+    pub fn step_over(&mut self) -> Result<StopReason> {
+        match decode(&self.memory, self.pc) {
+            Ok(instruction) if instruction.opcode == Opcode::Call => {
+                // the call comes back to the next instruction, with the stack
+                // as it is now
+                let (address, depth) = (self.pc + instruction.len(), self.stack.len());
+                self.run_until_return(address, depth)
             }
 
-            // jf: 8 a b
-            //   if <a> is zero, jump to <b>
-            8 => {
-                let a = self.next_argument();
-                let b = self.next_argument();
+            _ => Ok(match self.run_for(1)? {
+                StopReason::CycleLimit => StopReason::Stepped,
+                reason => reason,
+            }),
+        }
+    }
 
-                if self.load(a)? == 0 {
-                    jmp!(b);
+    // run with a temporary breakpoint at `address` until it's reached with
+    // `depth` entries on the stack, as recursive calls get there first
+    fn run_until_return(&mut self, address: usize, depth: usize) -> Result<StopReason> {
+        let temporary = self.breakpoints.insert(address);
+
+        let reason = loop {
+            match self.run_for(u64::MAX) {
+                Ok(StopReason::Breakpoint(pc)) if pc == address => {
+                    if self.stack.len() == depth {
+                        break Ok(StopReason::Stepped);
+                    } else if temporary {
+                        continue;
+                    }
+                    break Ok(StopReason::Breakpoint(pc));
                 }
+                reason => break reason,
             }
+        };
 
-            // add: 9 a b c
-            //   assign into <a> the sum of <b> and <c> (modulo 32768)
-            9 => {
-                let a = self.next_argument();
-                let b = self.next_argument();
-                let c = self.next_argument();
+        if temporary {
+            self.breakpoints.remove(&address);
+        }
+        reason
+    }
 
-                binary_operation!(a = b + c);
-            }
+    /// Run until the current function returns. There's no call stack apart
+    /// from the data stack, so that's taken to be the first `ret` that leaves
+    /// the stack shallower than it is now.
+    pub fn step_out(&mut self) -> Result<StopReason> {
+        let depth = self.stack.len();
+
+        loop {
+            let returning = matches!(
+                decode(&self.memory, self.pc),
+                Ok(Instruction {
+                    opcode: Opcode::Ret,
+                    ..
+                })
+            );
 
-            // mult: 10 a b c
-            //   store into <a> the product of <b> and <c> (modulo 32768)
-            10 => {
-                let a = self.next_argument();
-                let b = self.next_argument();
-                let c = self.next_argument();
+            match self.run_for(1)? {
+                StopReason::CycleLimit => {}
+                reason => return Ok(reason),
+            }
 
-                binary_operation!(a = b * c);
+            if returning && self.stack.len() < depth {
+                return Ok(StopReason::Stepped);
+            }
+            if self.breakpoints.contains(&self.pc) {
+                return Ok(StopReason::Breakpoint(self.pc));
             }
+        }
+    }
 
-            // mod: 11 a b c
-            //   store into <a> the remainder of <b> divided by <c>
-            11 => {
-                let a = self.next_argument();
-                let b = self.next_argument();
-                let c = self.next_argument();
+    fn do_cycle(&mut self) -> Result<()> {
+        if self.decode_cache.is_some() {
+            return self.do_cached_cycle();
+        }
 
-                // XXX: Does this want Rust-style remainder or C-style modulus?
-                binary_operation!(a = b % c);
-            }
+        macro_rules! run {
+            ($handler:ident) => {{
+                let operands = self.operands();
+                self.$handler(operands)
+            }};
+        }
 
-            // and: 12 a b c
-            //   stores into <a> the bitwise and of <b> and <c>
-            12 => {
-                let a = self.next_argument();
-                let b = self.next_argument();
-                let c = self.next_argument();
+        // Each opcode has its own handler, which gets the instruction's
+        // operands after pc has been moved past it. Matching on the opcode
+        // compiles down to a jump table, and unlike calling through an array
+        // of function pointers it lets the handlers be inlined.
+        let opcode = self.memory[self.pc];
+        match opcode {
+            0 => run!(halt),
+            1 => run!(set_op),
+            2 => run!(push),
+            3 => run!(pop),
+            4 => run!(eq),
+            5 => run!(gt),
+            6 => run!(jmp),
+            7 => run!(jt),
+            8 => run!(jf),
+            9 => run!(add),
+            10 => run!(mult),
+            11 => run!(mod_op),
+            12 => run!(and),
+            13 => run!(or),
+            14 => run!(not),
+            15 => run!(rmem),
+            16 => run!(wmem),
+            17 => run!(call),
+            18 => run!(ret),
+            19 => run!(out),
+            20 => run!(in_op),
+            21 => run!(noop),
+            _ => bail!(Error::UnknownOpcode(opcode)),
+        }
+    }
 
-                binary_operation!(a = b & c);
+    fn do_cached_cycle(&mut self) -> Result<()> {
+        let cache = self.decode_cache.as_mut().unwrap();
+        let instruction = match cache[self.pc] {
+            Some(instruction) => instruction,
+            None => {
+                let instruction = decode(&self.memory, self.pc)?;
+                cache[self.pc] = Some(instruction);
+                instruction
             }
+        };
+
+        self.pc += instruction.len();
+
+        let [a, b, c] = instruction.args;
+        match instruction.opcode {
+            Opcode::Halt => self.halt([]),
+            Opcode::Set => self.set_op([a, b]),
+            Opcode::Push => self.push([a]),
+            Opcode::Pop => self.pop([a]),
+            Opcode::Eq => self.eq([a, b, c]),
+            Opcode::Gt => self.gt([a, b, c]),
+            Opcode::Jmp => self.jmp([a]),
+            Opcode::Jt => self.jt([a, b]),
+            Opcode::Jf => self.jf([a, b]),
+            Opcode::Add => self.add([a, b, c]),
+            Opcode::Mult => self.mult([a, b, c]),
+            Opcode::Mod => self.mod_op([a, b, c]),
+            Opcode::And => self.and([a, b, c]),
+            Opcode::Or => self.or([a, b, c]),
+            Opcode::Not => self.not([a, b]),
+            Opcode::Rmem => self.rmem([a, b]),
+            Opcode::Wmem => self.wmem([a, b]),
+            Opcode::Call => self.call([a]),
+            Opcode::Ret => self.ret([]),
+            Opcode::Out => self.out([a]),
+            Opcode::In => self.in_op([a]),
+            Opcode::Noop => self.noop([]),
+        }
+    }
 
-            // or: 13 a b c
-            //   stores into <a> the bitwise or of <b> and <c>
-            13 => {
-                let a = self.next_argument();
-                let b = self.next_argument();
-                let c = self.next_argument();
+    /// Fetch the `N` operands of the instruction at pc and move pc past it.
+    #[inline]
+    fn operands<const N: usize>(&mut self) -> [u32; N] {
+        let pc = self.pc;
+        self.pc += 1 + N;
+        core::array::from_fn(|idx| self.memory[pc + 1 + idx])
+    }
 
-                binary_operation!(a = b | c);
-            }
+    #[inline]
+    fn jump(&mut self, location: u32) -> Result<()> {
+        self.pc = self.load(location)? as usize;
+        Ok(())
+    }
 
-            // not: 14 a b
-            //   stores 15-bit bitwise inverse of <b> in <a>
-            14 => {
-                let a = self.next_argument();
-                let b = self.next_argument();
+    #[inline]
+    fn bool_operation(&mut self, [a, b, c]: [u32; 3], op: impl Fn(u32, u32) -> bool) -> Result<()> {
+        if op(self.load(b)?, self.load(c)?) {
+            self.set(a, 1)
+        } else {
+            self.set(a, 0)
+        }
+    }
 
-                let b_value = self.load(b)?;
+    #[inline]
+    fn check_stack_depth(&self) -> Result<()> {
+        match self.max_stack_depth {
+            Some(max) if self.stack.len() >= max => bail!(Error::StackOverflow(max)),
+            _ => Ok(()),
+        }
+    }
 
-                self.set(a, (!b_value) & ((1 << INTEGER_SIZE) - 1))?;
-            }
+    #[inline]
+    fn binary_operation(
+        &mut self,
+        [a, b, c]: [u32; 3],
+        op: impl Fn(u32, u32) -> u32,
+    ) -> Result<()> {
+        let b = self.load(b)?;
+        let c = self.load(c)?;
+        let result = op(b, c) % MAX_VALUE;
+        self.set(a, result)
+    }
 
-            // rmem: 15 a b
-            //   read memory at address <b> and write it to <a>
-            15 => {
-                let a = self.next_argument();
-                let b = self.next_argument();
+    // halt: 0
+    //   stop execution and terminate the program
+    #[inline]
+    fn halt(&mut self, _: [u32; 0]) -> Result<()> {
+        bail!(Error::Halt)
+    }
 
-                let memory_value = self.memory[self.load(b)? as usize];
-                self.set(a, memory_value)?;
-            }
+    // set: 1 a b
+    //   set register <a> to the value of <b>
+    #[inline]
+    fn set_op(&mut self, [a, b]: [u32; 2]) -> Result<()> {
+        self.set(a, b)
+    }
 
-            // wmem: 16 a b
-            //   write the value from <b> into memory at address <a>
-            16 => {
-                let a = self.next_argument();
-                let b = self.next_argument();
+    // push: 2 a
+    //   push <a> onto the stack
+    #[inline]
+    fn push(&mut self, [a]: [u32; 1]) -> Result<()> {
+        let a_value = self.load(a)?;
+        self.check_stack_depth()?;
+        self.stack.push(a_value);
+        Ok(())
+    }
 
-                let memory_location = self.load(a)? as usize;
-                let b_value = self.load(b)?;
-                self.memory[memory_location] = b_value;
-            }
+    // pop: 3 a
+    //   remove the top element from the stack and write it into <a>; empty stack = error
+    #[inline]
+    fn pop(&mut self, [a]: [u32; 1]) -> Result<()> {
+        if let Some(tos) = self.stack.pop() {
+            self.set(a, tos)
+        } else {
+            bail!(Error::PopFromEmptyStack);
+        }
+    }
 
-            // call: 17 a
-            //   write the address of the next instruction to the stack and jump to <a>
-            17 => {
-                let a = self.next_argument();
-                self.stack.push(self.pc as u32);
-                jmp!(a);
-            }
+    // eq: 4 a b c
+    //   set <a> to 1 if <b> is equal to <c>; set it to 0 otherwise
+    #[inline]
+    fn eq(&mut self, args: [u32; 3]) -> Result<()> {
+        self.bool_operation(args, |b, c| b == c)
+    }
 
-            // ret: 18
-            //   remove the top element from the stack and jump to it; empty stack = halt
-            18 => {
-                if let Some(tos) = self.stack.pop() {
-                    jmp!(tos);
-                } else {
-                    bail!(Error::Halt);
-                }
-            }
+    // gt: 5 a b c
+    //   set <a> to 1 if <b> is greater than <c>; set it to 0 otherwise
+    #[inline]
+    fn gt(&mut self, args: [u32; 3]) -> Result<()> {
+        self.bool_operation(args, |b, c| b > c)
+    }
 
-            // out: 19 a
-            //   write the character represented by ascii code <a> to the terminal
-            19 => {
-                let a = self.next_argument();
+    // jmp: 6 a
+    //   jump to <a>
+    #[inline]
+    fn jmp(&mut self, [a]: [u32; 1]) -> Result<()> {
+        self.jump(a)
+    }
 
-                let ch = self.load(a)? as u8;
-                self.output.write_all(std::slice::from_ref(&ch))?;
-            }
+    // jt: 7 a b
+    //   if <a> is nonzero, jump to <b>
+    #[inline]
+    fn jt(&mut self, [a, b]: [u32; 2]) -> Result<()> {
+        if self.load(a)? != 0 {
+            self.jump(b)?;
+        }
 
-            // in: 20 a
-            //   read a character from the terminal and write its ascii code to <a>; it can be assumed that once input starts, it will continue until a newline is encountered; this means that you can safely read whole lines from the keyboard and trust that they will be fully read
-            20 => {
-                let a = self.next_argument();
+        Ok(())
+    }
 
-                let mut ch = 0;
+    // jf: 8 a b
+    //   if <a> is zero, jump to <b>
+    #[inline]
+    fn jf(&mut self, [a, b]: [u32; 2]) -> Result<()> {
+        if self.load(a)? == 0 {
+            self.jump(b)?;
+        }
 
-                loop {
-                    if let Err(..) = self.input.read_exact(std::slice::from_mut(&mut ch)) {
-                        bail!(Error::Halt);
-                    }
+        Ok(())
+    }
+
+    // add: 9 a b c
+    //   assign into <a> the sum of <b> and <c> (modulo 32768)
+    #[inline]
+    fn add(&mut self, args: [u32; 3]) -> Result<()> {
+        self.binary_operation(args, |b, c| b + c)
+    }
+
+    // mult: 10 a b c
+    //   store into <a> the product of <b> and <c> (modulo 32768)
+    #[inline]
+    fn mult(&mut self, args: [u32; 3]) -> Result<()> {
+        self.binary_operation(args, |b, c| b * c)
+    }
+
+    // mod: 11 a b c
+    //   store into <a> the remainder of <b> divided by <c>
+    #[inline]
+    fn mod_op(&mut self, args: [u32; 3]) -> Result<()> {
+        // values are never negative, so remainder and modulus agree
+        self.binary_operation(args, |b, c| b % c)
+    }
+
+    // and: 12 a b c
+    //   stores into <a> the bitwise and of <b> and <c>
+    #[inline]
+    fn and(&mut self, args: [u32; 3]) -> Result<()> {
+        self.binary_operation(args, |b, c| b & c)
+    }
+
+    // or: 13 a b c
+    //   stores into <a> the bitwise or of <b> and <c>
+    #[inline]
+    fn or(&mut self, args: [u32; 3]) -> Result<()> {
+        self.binary_operation(args, |b, c| b | c)
+    }
+
+    // not: 14 a b
+    //   stores 15-bit bitwise inverse of <b> in <a>
+    #[inline]
+    fn not(&mut self, [a, b]: [u32; 2]) -> Result<()> {
+        let b_value = self.load(b)?;
+
+        self.set(a, (!b_value) & ((1 << INTEGER_SIZE) - 1))
+    }
+
+    // rmem: 15 a b
+    //   read memory at address <b> and write it to <a>
+    #[inline]
+    fn rmem(&mut self, [a, b]: [u32; 2]) -> Result<()> {
+        let memory_location = self.load(b)? as usize;
+        let memory_value = self.memory[memory_location];
+        #[cfg(feature = "std")]
+        let memory_value = self.hooks.read(memory_location, memory_value);
+        self.set(a, memory_value)
+    }
+
+    // wmem: 16 a b
+    //   write the value from <b> into memory at address <a>
+    #[inline]
+    fn wmem(&mut self, [a, b]: [u32; 2]) -> Result<()> {
+        let memory_location = self.load(a)? as usize;
+        let b_value = self.load(b)?;
+        if !self.protected.is_empty()
+            && self
+                .protected
+                .iter()
+                .any(|range| range.contains(&memory_location))
+        {
+            bail!(Error::WriteToProtectedMemory(memory_location));
+        }
+        #[cfg(feature = "std")]
+        let b_value = match self.hooks.write(memory_location, b_value) {
+            Some(b_value) => b_value,
+            None => return Ok(()),
+        };
+        self.memory[memory_location] = b_value;
+
+        // Any instruction overlapping the written word must be decoded again
+        if let Some(cache) = &mut self.decode_cache {
+            let first = memory_location.saturating_sub(3);
+            cache[first..=memory_location]
+                .iter_mut()
+                .for_each(|instruction| *instruction = None);
+        }
+
+        Ok(())
+    }
+
+    // call: 17 a
+    //   write the address of the next instruction to the stack and jump to <a>
+    #[inline]
+    fn call(&mut self, [a]: [u32; 1]) -> Result<()> {
+        self.check_stack_depth()?;
+        self.stack.push(self.pc as u32);
+        self.jump(a)
+    }
+
+    // ret: 18
+    //   remove the top element from the stack and jump to it; empty stack = halt
+    #[inline]
+    fn ret(&mut self, _: [u32; 0]) -> Result<()> {
+        if let Some(tos) = self.stack.pop() {
+            self.jump(tos)
+        } else {
+            bail!(Error::ReturnFromEmptyStack);
+        }
+    }
+
+    // out: 19 a
+    //   write the character represented by ascii code <a> to the terminal
+    #[inline]
+    fn out(&mut self, [a]: [u32; 1]) -> Result<()> {
+        let value = self.load(a)?;
+        if self.strict && value > 255 {
+            bail!(Error::InvalidOutputChar(value));
+        }
+
+        let ch = value as u8;
+        let flush = match self.flush_policy {
+            FlushPolicy::Never => false,
+            FlushPolicy::EveryLine => ch == b'\n',
+            FlushPolicy::EveryChar => true,
+        };
 
-                    // Skip over the CR in windows' line ending
-                    if ch != b'\r' {
+        match &mut self.out_buffer {
+            Some(buffer) => {
+                buffer.push(ch);
+                if flush || buffer.len() >= OUT_BUFFER_LEN {
+                    self.write_out_buffer()?;
+                }
+            }
+            None => self.output.write_all(core::slice::from_ref(&ch))?,
+        }
+        if flush {
+            self.output.flush()?;
+        }
+        Ok(())
+    }
+
+    // in: 20 a
+    //   read a character from the terminal and write its ascii code to <a>; it can be assumed that once input starts, it will continue until a newline is encountered; this means that you can safely read whole lines from the keyboard and trust that they will be fully read
+    #[inline]
+    fn in_op(&mut self, [a]: [u32; 1]) -> Result<()> {
+        // the program's likely waiting on an answer to what it just printed
+        self.write_out_buffer()?;
+
+        let mut ch = 0;
+
+        loop {
+            if self
+                .input
+                .read_exact(core::slice::from_mut(&mut ch))
+                .is_err()
+            {
+                match self.input_eof {
+                    InputEof::Halt => bail!(Error::Halt),
+                    InputEof::Strict if self.partial_line_len > 0 => {
+                        bail!(Error::PartialInput(self.partial_line_len))
+                    }
+                    InputEof::Strict => bail!(Error::Halt),
+                    InputEof::Error => bail!(Error::InputExhausted),
+                    InputEof::Block => {
+                        #[cfg(feature = "std")]
+                        std::thread::yield_now();
+                        #[cfg(not(feature = "std"))]
+                        core::hint::spin_loop();
+                        continue;
+                    }
+                    InputEof::Sentinel(sentinel) => {
+                        ch = sentinel;
                         break;
                     }
                 }
-
-                self.set(a, u32::from(ch))?;
             }
 
-            // noop: 21
-            //   no operation
-            21 => { /* do nothing */ }
-
-            unknown_opcode => {
-                bail!(Error::UnknownOpcode(unknown_opcode));
+            // Skip over the CR in windows' line ending, unless asked not to
+            if ch != b'\r' || self.input_newline == InputNewlineMode::Raw {
+                break;
             }
         }
 
+        if ch == b'\n' {
+            self.partial_line_len = 0;
+        } else {
+            self.partial_line_len += 1;
+        }
+        self.set(a, u32::from(ch))
+    }
+
+    // noop: 21
+    //   no operation
+    #[inline]
+    fn noop(&mut self, _: [u32; 0]) -> Result<()> {
         Ok(())
     }
 }
 
+#[cfg(feature = "std")]
 impl<Output: Write> VM<io::Cursor<Vec<u8>>, Output> {
     pub fn append_input<B: AsRef<[u8]>>(&mut self, buf: B) -> Result<()> {
         use io::{Seek, SeekFrom};
@@ -461,15 +1380,140 @@ impl<Output: Write> VM<io::Cursor<Vec<u8>>, Output> {
     }
 }
 
+#[cfg(feature = "std")]
 impl<Input: Read> VM<Input, io::Cursor<Vec<u8>>> {
+    /// Run for at most `max_cycles` cycles like [`VM::run_for`], returning
+    /// the output drained afterwards as lossy UTF-8 along with why it
+    /// stopped. If the cap was reached, calling this again picks up where it
+    /// left off.
+    ///
+    /// The output comes back even when running failed, since what was
+    /// printed on the way to the error is usually what explains it, which is
+    /// why the reason is a [`Result`] rather than a bare [`StopReason`].
+    ///
+    /// [`InputEof::Block`] is treated like [`InputEof::Error`] here, as it
+    /// would wait on the `in` without the wait counting towards the cap.
+    pub fn run_capped(&mut self, max_cycles: u64) -> (String, Result<StopReason>) {
+        let input_eof = self.input_eof;
+        if input_eof == InputEof::Block {
+            self.input_eof = InputEof::Error;
+        }
+        let result = self.run_for(max_cycles);
+        self.input_eof = input_eof;
+
+        let result = match result {
+            Err(err) if matches!(vm_error(&err), Some(Error::InputExhausted)) => {
+                Ok(StopReason::NeedsInput)
+            }
+            // running out of input halts by default, with the `in` left to
+            // retry, which a real halt can't look like
+            Ok(StopReason::Halted)
+                if Opcode::from_u32(self.memory[self.pc]) == Some(Opcode::In) =>
+            {
+                Ok(StopReason::NeedsInput)
+            }
+            result => result,
+        };
+
+        let output = self.drain_output().collect::<Vec<_>>();
+        (String::from_utf8_lossy(&output).into_owned(), result)
+    }
+
+    /// Take everything written to the output since the last call, leaving
+    /// the buffer empty so that it doesn't keep growing.
+    pub fn drain_output(&mut self) -> impl Iterator<Item = u8> + '_ {
+        self.output.set_position(0);
+        self.output.get_mut().drain(..)
+    }
+
     pub fn cycle_until_next_room(&mut self) -> Result<(String, Option<crate::Room>)> {
+        self.cycle_until_next_room_in(|vm| &mut vm.output)
+    }
+
+    /// Run until the output written from here on contains `needle`, the
+    /// program halts or `max_cycles` cycles have run, e.g. to wait for a
+    /// message that isn't followed by the prompt.
+    pub fn run_until_output_contains(
+        &mut self,
+        needle: &[u8],
+        max_cycles: u64,
+    ) -> Result<StopReason> {
         let pos = usize::try_from(self.output.position())?;
+        let mut detector = crate::NeedleDetector::new(needle, pos);
+
+        for _ in 0..max_cycles {
+            if detector.update(self.output.get_ref()) {
+                return Ok(StopReason::OutputMatched);
+            }
 
-        while !self.output.get_ref()[pos..].ends_with(b"What do you do?") {
             match self.cycle() {
                 Ok(()) => {}
                 Err(err) => {
-                    if let Some(Error::Halt) = err.downcast_ref::<Error>() {
+                    if vm_error(&err).is_some_and(Error::is_halt) {
+                        // what was buffered up only arrives now
+                        return Ok(if detector.update(self.output.get_ref()) {
+                            StopReason::OutputMatched
+                        } else {
+                            StopReason::Halted
+                        });
+                    }
+
+                    bail!(err);
+                }
+            }
+        }
+
+        self.write_out_buffer()?;
+        Ok(if detector.update(self.output.get_ref()) {
+            StopReason::OutputMatched
+        } else {
+            StopReason::CycleLimit
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl VM<io::Cursor<Vec<u8>>, io::Cursor<Vec<u8>>> {
+    /// Type `command` into the game, newline and all, and run until it asks
+    /// for the next one. Trailing whitespace is trimmed off, and since the
+    /// game reads a whole line at a time, `command` can't span several.
+    pub fn send_command(&mut self, command: &str) -> Result<(String, Option<crate::Room>)> {
+        let command = command.trim_end();
+        if command.contains('\n') {
+            bail!(eyre!("{:?} is more than one command", command));
+        }
+
+        self.append_input(command)?;
+        self.append_input("\n")?;
+        self.cycle_until_next_room()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<Input: Read, Output: Write> VM<Input, crate::Tee<Output>> {
+    /// Like [`VM::cycle_until_next_room`] on an in-memory output, for any
+    /// output wrapped in a [`Tee`](crate::Tee).
+    pub fn cycle_until_next_room(&mut self) -> Result<(String, Option<crate::Room>)> {
+        self.cycle_until_next_room_in(|vm| &mut vm.output.copy)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<Input: Read, Output: Write> VM<Input, Output> {
+    // run until the output, as seen in `buffer`, ends with the prompt and
+    // then parse whatever room was described since the last time
+    fn cycle_until_next_room_in(
+        &mut self,
+        buffer: fn(&mut Self) -> &mut io::Cursor<Vec<u8>>,
+    ) -> Result<(String, Option<crate::Room>)> {
+        let pos = usize::try_from(buffer(self).position())?;
+        let mut prompt = crate::PromptDetector::new(pos);
+
+        while !prompt.update(buffer(self).get_ref()) {
+            match self.cycle() {
+                Ok(()) => {}
+                Err(err) => {
+                    if vm_error(&err).is_some_and(Error::is_halt) {
                         break;
                     }
 
@@ -478,50 +1522,619 @@ impl<Input: Read> VM<Input, io::Cursor<Vec<u8>>> {
             }
         }
 
-        self.output.set_position(pos as u64);
+        let buffer = buffer(self);
+        buffer.set_position(pos as u64);
 
-        Ok(crate::Room::parse(&mut self.output)?)
+        let (prelude, room) = crate::Room::parse(buffer)?;
+        // commands that don't lead anywhere, like `take`, leave us where we were
+        if room.is_some() {
+            self.current_room.clone_from(&room);
+        }
+        Ok((prelude, room))
+    }
+
+    /// The room [`VM::cycle_until_next_room`] last came across, which is
+    /// where the player is as long as nothing else has run the VM since.
+    pub fn current_room(&self) -> Option<&crate::Room> {
+        self.current_room.as_ref()
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
-    use std::convert::TryFrom;
-
-    use proptest::prelude::*;
+    use std::io;
 
     use super::*;
 
-    proptest! {
-        #[test]
-        fn test_roundtrip(
-            memory in vec![any::<u32>(); ADDRESS_SPACE],
-            registers in vec![any::<u32>(); REGISTER_COUNT],
-            stack: Vec<u32>,
-            pc: usize,
-        ) {
-            let memory = <[u32; ADDRESS_SPACE]>::try_from(memory).unwrap();
-            let registers = <[u32; REGISTER_COUNT]>::try_from(registers).unwrap();
-
-            let vm = Box::new(VM {
-                memory,
-                registers,
-                stack,
-                pc,
-                input: io::Cursor::new(vec![]), output:
-                io::Cursor::new(vec![])
-            });
-
-            let mut buf = Vec::new();
-            vm.save_snapshot(&mut buf).unwrap();
-            prop_assert_eq!(
-                VM::load_snapshot(
-                    io::Cursor::new(vec![]),
-                    io::Cursor::new(vec![]),
-                    io::Cursor::new(buf)
-                ).unwrap(),
-                vm
-            );
+    type TestVM = VM<io::Cursor<Vec<u8>>, io::Cursor<Vec<u8>>>;
+
+    fn vm_with_program(program: &[u32]) -> Box<TestVM> {
+        VM::from_words(
+            io::Cursor::new(vec![]),
+            io::Cursor::new(vec![]),
+            program.iter().copied(),
+        )
+    }
+
+    #[test]
+    fn test_memory_hook() {
+        struct Device {
+            written: Vec<u32>,
+        }
+
+        impl MemoryHook for Device {
+            fn on_read(&mut self, _address: usize, _value: u32) -> Option<u32> {
+                Some(42)
+            }
+
+            fn on_write(&mut self, _address: usize, value: u32) -> Option<u32> {
+                self.written.push(value);
+                None
+            }
+        }
+
+        // wmem 100 7; rmem r0 100; halt
+        let mut vm = vm_with_program(&[16, 100, 7, 15, 32768, 100, 0]);
+        let device = Arc::new(Mutex::new(Device {
+            written: Vec::new(),
+        }));
+        vm.add_memory_hook(100..101, device.clone());
+
+        vm.run_to_halt().unwrap();
+
+        assert_eq!(vm.memory[100], 0);
+        assert_eq!(vm.registers[0], 42);
+        assert_eq!(device.lock().unwrap().written, [7]);
+    }
+
+    #[test]
+    fn test_input_eof_strict() {
+        // in r0; jmp 0
+        let mut vm = vm_with_program(&[20, 32768, 6, 0]);
+        vm.append_input("look\nta").unwrap();
+        vm.run_to_halt().unwrap();
+        assert_eq!(vm.partial_line_len(), 2);
+
+        let mut vm = vm_with_program(&[20, 32768, 6, 0]);
+        vm.input_eof = InputEof::Strict;
+        vm.append_input("look\nta").unwrap();
+        let err = vm.run_to_halt().unwrap_err();
+        assert!(matches!(vm_error(&err), Some(Error::PartialInput(2))));
+
+        let mut vm = vm_with_program(&[20, 32768, 6, 0]);
+        vm.input_eof = InputEof::Strict;
+        vm.append_input("look\n").unwrap();
+        vm.run_to_halt().unwrap();
+        assert_eq!(vm.partial_line_len(), 0);
+    }
+
+    #[test]
+    fn test_input_eof_error() {
+        // in r0; halt
+        let mut vm = vm_with_program(&[20, 32768, 0]);
+        vm.input_eof = InputEof::Error;
+
+        let err = vm.cycle().unwrap_err();
+        assert!(matches!(vm_error(&err), Some(Error::InputExhausted)));
+        assert_eq!(vm.pc, 0);
+
+        vm.append_input("x").unwrap();
+        vm.run_to_halt().unwrap();
+        assert_eq!(vm.registers[0], u32::from(b'x'));
+    }
+
+    #[test]
+    fn test_input_newline_mode() {
+        // in r0; in r1; in r2; halt
+        let program = [20, 32768, 20, 32769, 20, 32770, 0];
+
+        let mut vm = vm_with_program(&program);
+        vm.append_input("a\r\nb").unwrap();
+        vm.run_to_halt().unwrap();
+        assert_eq!(
+            &vm.registers[..3],
+            [u32::from(b'a'), u32::from(b'\n'), u32::from(b'b')]
+        );
+
+        let mut vm = vm_with_program(&program);
+        vm.input_newline = InputNewlineMode::Raw;
+        vm.append_input("a\r\nb").unwrap();
+        vm.run_to_halt().unwrap();
+        assert_eq!(
+            &vm.registers[..3],
+            [u32::from(b'a'), u32::from(b'\r'), u32::from(b'\n')]
+        );
+    }
+
+    #[test]
+    fn test_opcode_histogram() {
+        // loop: add r0 r0 1
+        //       eq r1 r0 3
+        //       jf r1 loop
+        //       out 'A'
+        //       halt
+        let program = [
+            9, 32768, 32768, 1, 4, 32769, 32768, 3, 8, 32769, 0, 19, 65, 0,
+        ];
+
+        let mut vm = vm_with_program(&program);
+        vm.run_to_halt().unwrap();
+        assert!(vm.opcode_histogram().is_empty());
+
+        let mut vm = vm_with_program(&program);
+        vm.enable_opcode_histogram();
+        vm.run_to_halt().unwrap();
+        let histogram = vm.opcode_histogram();
+        assert_eq!(histogram.len(), 4);
+        assert_eq!(histogram[&Opcode::Add], 3);
+        assert_eq!(histogram[&Opcode::Jf], 3);
+        assert_eq!(histogram[&Opcode::Out], 1);
+        assert_eq!(histogram.values().sum::<u64>(), vm.instruction_count);
+    }
+
+    #[test]
+    fn test_set_pc() {
+        // out 'A'; halt
+        let mut vm = vm_with_program(&[19, 65, 0]);
+        vm.set_pc(2).unwrap();
+        assert_eq!(vm.pc, 2);
+        vm.set_pc(1).unwrap();
+        assert!(vm.set_pc(ADDRESS_SPACE).is_err());
+
+        // 65 isn't an opcode
+        vm.strict = true;
+        let err = vm.set_pc(1).unwrap_err();
+        assert!(matches!(vm_error(&err), Some(Error::UnknownOpcode(65))));
+        assert_eq!(vm.pc, 1);
+        vm.set_pc(0).unwrap();
+    }
+
+    #[test]
+    fn test_flush_policy() {
+        // counts the bytes written and the flushes
+        #[derive(Default)]
+        struct Counter {
+            written: usize,
+            flushes: Vec<usize>,
         }
+
+        impl Write for Counter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.written += buf.len();
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                self.flushes.push(self.written);
+                Ok(())
+            }
+        }
+
+        // out 'A'; out '\n'; out 'B'; halt
+        let program = [19, 65, 19, 10, 19, 66, 0];
+        let run = |policy| {
+            let mut vm = VM::from_words(io::empty(), Counter::default(), program);
+            vm.flush_policy = policy;
+            vm.run_to_halt().unwrap();
+            vm.output.flushes
+        };
+        assert_eq!(run(FlushPolicy::Never), []);
+        assert_eq!(run(FlushPolicy::EveryChar), [1, 2, 3]);
+        assert_eq!(run(FlushPolicy::EveryLine), [2]);
+    }
+
+    #[test]
+    fn test_output_buffering() {
+        // keeps every write separately
+        #[derive(Default)]
+        struct Writes(Vec<Vec<u8>>);
+
+        impl Write for Writes {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.push(buf.to_vec());
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        // out 'A'; out '\n'; out 'B'; in r0; out 'C'; halt
+        let program = [19, 65, 19, 10, 19, 66, 20, 32768, 19, 67, 0];
+        let run = |policy| {
+            let mut vm = VM::from_words(io::Cursor::new(vec![b'x']), Writes::default(), program);
+            vm.flush_policy = policy;
+            vm.enable_output_buffering();
+            vm.run_to_halt().unwrap();
+            vm.output.0
+        };
+        assert_eq!(run(FlushPolicy::Never), [&b"A\nB"[..], b"C"]);
+        assert_eq!(run(FlushPolicy::EveryLine), [&b"A\n"[..], b"B", b"C"]);
+
+        let mut vm = VM::from_words(io::empty(), Writes::default(), program);
+        vm.enable_output_buffering();
+        assert_eq!(vm.run_for(2).unwrap(), StopReason::CycleLimit);
+        assert_eq!(vm.output.0, [b"A\n"]);
+    }
+
+    #[test]
+    fn test_strict_out() {
+        // out 300; halt
+        let mut vm = vm_with_program(&[19, 300, 0]);
+        vm.strict = true;
+
+        let err = vm.cycle().unwrap_err();
+        assert!(matches!(
+            vm_error(&err),
+            Some(Error::InvalidOutputChar(300))
+        ));
+        assert!(vm.output.get_ref().is_empty());
+    }
+
+    #[test]
+    fn test_protect() {
+        // wmem 10 1; wmem 20 2; halt
+        let mut vm = vm_with_program(&[16, 10, 1, 16, 20, 2, 0]);
+        vm.protect(5..25);
+        vm.unprotect(8..15);
+
+        vm.cycle().unwrap();
+        assert_eq!(vm.memory[10], 1);
+
+        let err = vm.cycle().unwrap_err();
+        assert!(matches!(
+            vm_error(&err),
+            Some(Error::WriteToProtectedMemory(20))
+        ));
+        assert_eq!(vm.memory[20], 0);
+    }
+
+    #[test]
+    fn test_find() {
+        let mut vm = VM::load_program(
+            io::Cursor::new(vec![]),
+            io::Cursor::new(vec![]),
+            include_bytes!("challenge.bin"),
+        );
+
+        // the greeting is printed one `out` at a time
+        let greeting = b"Welcome"
+            .iter()
+            .flat_map(|&ch| [19, u32::from(ch)])
+            .collect::<Vec<_>>();
+        assert_eq!(vm.find_bytes(&greeting), [2]);
+
+        // the room names only show up once the program has decrypted them
+        assert!(vm.find_str("Foothills").is_empty());
+        vm.input_eof = InputEof::Error;
+        vm.run_to_halt().unwrap_err();
+        assert!(!vm.find_str("Foothills").is_empty());
+    }
+
+    #[test]
+    fn test_drain_output() {
+        // out 'A'; out 'B'; in r0; out 'C'; halt
+        let mut vm = vm_with_program(&[19, 65, 19, 66, 20, 32768, 19, 67, 0]);
+        vm.input_eof = InputEof::Error;
+
+        vm.run_to_halt().unwrap_err();
+        assert_eq!(vm.drain_output().collect::<Vec<_>>(), b"AB");
+        assert_eq!(vm.drain_output().count(), 0);
+
+        vm.append_input("\n").unwrap();
+        vm.run_to_halt().unwrap();
+        assert_eq!(vm.drain_output().collect::<Vec<_>>(), b"C");
+    }
+
+    #[test]
+    fn test_run_capped() {
+        // loop: out 'A'
+        //       jmp loop
+        let mut vm = vm_with_program(&[19, 65, 6, 0]);
+
+        let (output, reason) = vm.run_capped(5);
+        assert_eq!(output, "AAA");
+        assert_eq!(reason.unwrap(), StopReason::CycleLimit);
+
+        let (output, reason) = vm.run_capped(2);
+        assert_eq!(output, "A");
+        assert_eq!(reason.unwrap(), StopReason::CycleLimit);
+
+        // out 'A'; in r0; halt
+        let program = [19, 65, 20, 32768, 0];
+        for input_eof in [
+            InputEof::Halt,
+            InputEof::Error,
+            InputEof::Block,
+            InputEof::Strict,
+        ] {
+            let mut vm = vm_with_program(&program);
+            vm.input_eof = input_eof;
+            let (output, reason) = vm.run_capped(10);
+            assert_eq!(output, "A");
+            assert_eq!(reason.unwrap(), StopReason::NeedsInput);
+            assert_eq!(vm.pc, 2);
+            assert_eq!(vm.input_eof, input_eof);
+        }
+
+        let mut vm = vm_with_program(&program);
+        vm.append_input("x").unwrap();
+        let (output, reason) = vm.run_capped(10);
+        assert_eq!(output, "A");
+        assert_eq!(reason.unwrap(), StopReason::Halted);
+
+        // out 'A'; <unknown opcode>
+        let mut vm = vm_with_program(&[19, 65, 99]);
+        let (output, reason) = vm.run_capped(10);
+        assert_eq!(output, "A");
+        let err = reason.unwrap_err();
+        assert!(matches!(vm_error(&err), Some(Error::UnknownOpcode(99))));
+    }
+
+    #[test]
+    fn test_halt_and_empty_ret() {
+        let err = vm_with_program(&[0]).cycle().unwrap_err();
+        assert!(matches!(vm_error(&err), Some(Error::Halt)));
+
+        let mut vm = vm_with_program(&[18]);
+        let err = vm.cycle().unwrap_err();
+        assert!(matches!(vm_error(&err), Some(Error::ReturnFromEmptyStack)));
+        vm.run_to_halt().unwrap();
+    }
+
+    #[test]
+    fn test_state_hash() {
+        let mut vm = vm_with_program(&[21, 0]);
+        let hash = vm.state_hash();
+        assert_eq!(vm.clone().state_hash(), hash);
+
+        vm.set_register(Register::R3, 1);
+        assert_ne!(vm.state_hash(), hash);
+        vm.set_register(Register::R3, 0);
+        vm.stack.push(0);
+        assert_ne!(vm.state_hash(), hash);
+        vm.stack.pop();
+        assert_eq!(vm.state_hash(), hash);
+    }
+
+    #[test]
+    fn test_tee_next_room() {
+        let text = "Hello.\n== Room ==\nA room.\n\nWhat do you do?";
+        let mut program = Vec::new();
+        for &ch in text.as_bytes() {
+            program.extend([19, u32::from(ch)]);
+        }
+        program.push(0);
+
+        let mut vm = VM::from_words(io::empty(), crate::Tee::new(Vec::new()), program);
+        let (prelude, room) = vm.cycle_until_next_room().unwrap();
+        assert_eq!(prelude, "Hello.\n");
+        assert_eq!(room.unwrap().title, "Room");
+        assert_eq!(vm.output.inner, text.as_bytes());
+        assert_eq!(vm.current_room().unwrap().title, "Room");
+    }
+
+    #[test]
+    fn test_reload_program() {
+        // out 'A'; halt
+        let mut vm = vm_with_program(&[19, 65, 0, 21, 21]);
+        vm.enable_decode_cache();
+        vm.run_to_halt().unwrap();
+        vm.set_register(Register::R1, 7);
+
+        // out 'B'; halt
+        vm.reload_program(&[19, 0, 66, 0, 0, 0]).unwrap();
+        assert_eq!((vm.pc, vm.registers, vm.instruction_count), (0, [0; 8], 0));
+        assert_eq!(&vm.memory[..5], [19, 66, 0, 0, 0]);
+        vm.run_to_halt().unwrap();
+        assert_eq!(vm.output.get_ref(), b"AB");
+
+        assert!(vm.reload_program(&[0]).is_err());
+    }
+
+    #[test]
+    fn test_no_progress() {
+        // loop: jt r0 loop
+        //       out 'A'
+        let mut vm = vm_with_program(&[7, 32768, 0, 19, 65]);
+        vm.set_register(Register::R0, 1);
+        assert_eq!(vm.run_for(100).unwrap(), StopReason::CycleLimit);
+        vm.no_progress_window = Some(16);
+        assert_eq!(vm.run_for(100).unwrap(), StopReason::NoProgress(0));
+
+        // counting r0 up to 10 changes the state every time round
+        // loop: add r0 r0 1
+        //       eq r1 r0 10
+        //       jf r1 loop
+        //       halt
+        let mut vm = vm_with_program(&[9, 32768, 32768, 1, 4, 32769, 32768, 10, 8, 32769, 0, 0]);
+        vm.no_progress_window = Some(2);
+        assert_eq!(vm.run_for(100).unwrap(), StopReason::Halted);
+    }
+
+    #[test]
+    fn test_load_program_at() {
+        // out 'A'; halt
+        let program = [19, 0, 65, 0, 0, 0];
+        let mut vm = VM::load_program_at(io::empty(), Vec::new(), &program, 100);
+        assert_eq!(vm.pc, 100);
+        assert_eq!(&vm.memory[99..104], [0, 19, 65, 0, 0]);
+        vm.run_to_halt().unwrap();
+        assert_eq!(vm.output, b"A");
+    }
+
+    #[test]
+    fn test_send_command() {
+        // echo each line back, then prompt
+        let mut program = vec![20, 32768, 19, 32768, 4, 32769, 32768, 10, 8, 32769, 0];
+        for &ch in b"What do you do?" {
+            program.extend([19, u32::from(ch)]);
+        }
+        program.extend([6, 0]);
+
+        let mut vm = vm_with_program(&program);
+        let (prelude, room) = vm.send_command("look \t").unwrap();
+        assert_eq!(prelude, "look\nWhat do you do?");
+        assert!(room.is_none());
+        assert!(vm.send_command("north\nsouth").is_err());
+    }
+
+    #[test]
+    fn test_run_until_output_contains() {
+        // out 'a'; out 'b'; jmp 0
+        let mut vm = vm_with_program(&[19, 97, 19, 98, 6, 0]);
+        assert_eq!(
+            vm.run_until_output_contains(b"bab", 100).unwrap(),
+            StopReason::OutputMatched
+        );
+        assert_eq!(vm.output.get_ref(), b"abab");
+
+        assert_eq!(
+            vm.run_until_output_contains(b"bb", 100).unwrap(),
+            StopReason::CycleLimit
+        );
+
+        // out 'a'; halt
+        let mut vm = vm_with_program(&[19, 97, 0]);
+        assert_eq!(
+            vm.run_until_output_contains(b"b", 100).unwrap(),
+            StopReason::Halted
+        );
+
+        // buffered, the needle and what follows it arrive in one write: at
+        // the halt, or once the cycles are up
+        // out 'a'; out 'b'; out 'c'; out 'd'; halt
+        let mut vm = vm_with_program(&[19, 97, 19, 98, 19, 99, 19, 100, 0]);
+        vm.enable_output_buffering();
+        assert_eq!(
+            vm.run_until_output_contains(b"bc", 100).unwrap(),
+            StopReason::OutputMatched
+        );
+        assert_eq!(vm.output.get_ref(), b"abcd");
+
+        // out 'a'; out 'b'; out 'c'; out 'd'; noop; jmp 8
+        let mut vm = vm_with_program(&[19, 97, 19, 98, 19, 99, 19, 100, 21, 6, 8]);
+        vm.enable_output_buffering();
+        assert_eq!(
+            vm.run_until_output_contains(b"bc", 20).unwrap(),
+            StopReason::OutputMatched
+        );
+    }
+
+    // counts r0 down to zero, recursing once per step
+    fn recursive_program() -> Box<TestVM> {
+        vm_with_program(&[
+            1, 32768, 2, // 0: set r0 2
+            17, 10, // 3: call 10
+            19, 65, // 5: out 'A'
+            0,  // 7: halt
+            21, 21, // 8: noop; noop
+            8, 32768, 20, // 10: jf r0 20
+            9, 32768, 32768, 32767, // 13: add r0 r0 -1
+            17, 10, // 17: call 10
+            18, // 19: ret
+            18, // 20: ret
+        ])
+    }
+
+    #[test]
+    fn test_step_over_and_out() {
+        let mut vm = recursive_program();
+        assert_eq!(vm.step_over().unwrap(), StopReason::Stepped);
+        assert_eq!(vm.pc, 3);
+        assert_eq!(vm.step_over().unwrap(), StopReason::Stepped);
+        assert_eq!((vm.pc, vm.registers[0], vm.stack.len()), (5, 0, 0));
+        assert!(vm.breakpoints().next().is_none());
+
+        // the recursive calls get back to 19 first, with more on the stack
+        let mut vm = recursive_program();
+        vm.add_breakpoint(17);
+        vm.run_for(100).unwrap();
+        vm.remove_breakpoint(17);
+        assert_eq!((vm.pc, vm.stack.len()), (17, 1));
+        assert_eq!(vm.step_over().unwrap(), StopReason::Stepped);
+        assert_eq!((vm.pc, vm.stack.len()), (19, 1));
+
+        // stepping out of the innermost call lands after the call to it
+        let mut vm = recursive_program();
+        vm.add_breakpoint(20);
+        vm.run_for(100).unwrap();
+        assert_eq!((vm.pc, vm.stack.len()), (20, 3));
+        assert_eq!(vm.step_out().unwrap(), StopReason::Stepped);
+        assert_eq!((vm.pc, vm.stack.len()), (19, 2));
+        assert_eq!(vm.step_out().unwrap(), StopReason::Stepped);
+        assert_eq!((vm.pc, vm.stack.len()), (19, 1));
+
+        // a breakpoint inside the call stops step_over early
+        let mut vm = recursive_program();
+        vm.run_for(1).unwrap();
+        vm.add_breakpoint(13);
+        assert_eq!(vm.step_over().unwrap(), StopReason::Breakpoint(13));
+    }
+
+    #[test]
+    fn test_mod() {
+        for (b, c, expected) in [
+            (0, 1, 0),
+            (1, 1, 0),
+            (7, 3, 1),
+            (3, 7, 3),
+            (7, 7, 0),
+            (32767, 1, 0),
+            (32767, 2, 1),
+            (32767, 32767, 0),
+            (1, 32767, 1),
+            (32766, 32767, 32766),
+        ] {
+            // mod r0 b c
+            let mut vm = vm_with_program(&[11, 32768, b, c]);
+            vm.cycle().unwrap();
+            assert_eq!(vm.registers[0], expected, "{} mod {}", b, c);
+        }
+
+        // mod r0 r1 r2, with operands coming from registers
+        let mut vm = vm_with_program(&[11, 32768, 32769, 32770]);
+        vm.registers[1] = 32767;
+        vm.registers[2] = 10;
+        vm.cycle().unwrap();
+        assert_eq!(vm.registers[0], 7);
+    }
+
+    #[test]
+    fn test_stack_depth() {
+        // loop: push 1
+        //       jmp loop
+        let mut vm = vm_with_program(&[2, 1, 6, 0]);
+        vm.stack_watchpoint = Some(3);
+        vm.max_stack_depth = Some(5);
+
+        assert_eq!(vm.run_for(100).unwrap(), StopReason::StackDepth(3));
+        let err = vm.run_for(100).unwrap_err();
+        assert!(matches!(vm_error(&err), Some(Error::StackOverflow(5))));
+        assert_eq!(vm.stack.len(), 5);
+    }
+
+    #[test]
+    fn test_decode_cache_sees_wmem() {
+        // loop: out 'A'
+        //       wmem 1 'B'
+        //       add r0 r0 1
+        //       eq r1 r0 2
+        //       jf r1 loop
+        //       halt
+        let mut vm = vm_with_program(&[
+            19, 65, //
+            16, 1, 66, //
+            9, 32768, 32768, 1, //
+            4, 32769, 32768, 2, //
+            8, 32769, 0, //
+            0,
+        ]);
+        vm.enable_decode_cache();
+
+        vm.run_to_halt().unwrap();
+
+        assert_eq!(vm.output.get_ref(), b"AB");
     }
 }
@@ -0,0 +1,73 @@
+//! A [`wasm_bindgen`] wrapper around the VM, so that JavaScript can drive it:
+//! feed it input, run it for a while, and collect whatever it printed.
+
+use alloc::{boxed::Box, collections::VecDeque, string::ToString, vec::Vec};
+
+use wasm_bindgen::prelude::*;
+
+use crate::{program_words, vm_error, Error, InputEof, StopReason, VM};
+
+/// Why a call to [`WasmVM::run_for`] returned.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// The cycle cap was reached, so there's more to run.
+    Running,
+
+    /// The program is waiting for input.
+    NeedsInput,
+
+    /// The program halted.
+    Halted,
+}
+
+#[wasm_bindgen]
+pub struct WasmVM {
+    vm: Box<VM<VecDeque<u8>, Vec<u8>>>,
+}
+
+#[wasm_bindgen]
+impl WasmVM {
+    /// Load a little-endian program image, like `challenge.bin`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(program: &[u8]) -> WasmVM {
+        let mut vm = VM::from_words(VecDeque::new(), Vec::new(), program_words(program));
+        vm.input_eof = InputEof::Error;
+        WasmVM { vm }
+    }
+
+    /// Queue up `bytes` for the program to read.
+    pub fn feed_input(&mut self, bytes: &[u8]) {
+        self.vm.input.extend(bytes);
+    }
+
+    /// Take everything the program has printed since the last call.
+    pub fn drain_output(&mut self) -> Vec<u8> {
+        core::mem::take(&mut self.vm.output)
+    }
+
+    /// Run a single instruction.
+    pub fn step(&mut self) -> Result<Status, JsValue> {
+        self.run_for(1)
+    }
+
+    /// Run for at most `cycles` instructions.
+    pub fn run_for(&mut self, cycles: u32) -> Result<Status, JsValue> {
+        match self.vm.run_for(u64::from(cycles)) {
+            Ok(StopReason::Halted) => Ok(Status::Halted),
+            Ok(StopReason::NeedsInput) => Ok(Status::NeedsInput),
+            Ok(
+                StopReason::CycleLimit
+                | StopReason::Breakpoint(_)
+                | StopReason::StackDepth(_)
+                | StopReason::Stepped
+                | StopReason::NoProgress(_)
+                | StopReason::OutputMatched,
+            ) => Ok(Status::Running),
+            Err(err) => match vm_error(&err) {
+                Some(Error::InputExhausted) => Ok(Status::NeedsInput),
+                _ => Err(JsValue::from_str(&err.to_string())),
+            },
+        }
+    }
+}
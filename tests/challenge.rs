@@ -0,0 +1,113 @@
+use std::io::{self, Cursor};
+
+use synacor_vm::{explore, StopReason, VM};
+
+const CHALLENGE: &[u8] = include_bytes!("../src/challenge.bin");
+
+// plenty for the self-test and the first room, while making sure a broken
+// interpreter can't spin forever
+const MAX_CYCLES: u64 = 10_000_000;
+
+#[test]
+fn test_challenge_opening() {
+    let mut vm = VM::load_program(io::empty(), Cursor::new(Vec::new()), CHALLENGE);
+
+    // with no input, the program halts at the first prompt
+    assert_eq!(vm.run_for(MAX_CYCLES).unwrap(), StopReason::Halted);
+
+    let output = String::from_utf8(vm.output.into_inner()).unwrap();
+    assert!(
+        output.contains("self-test complete, all tests pass"),
+        "{}",
+        output
+    );
+    assert!(output.contains("The self-test completion code is: "));
+    assert!(output.contains("== Foothills =="));
+    assert!(output.trim_end().ends_with("What do you do?"));
+}
+
+/// Where a run ended up, boiled down to a couple of hashes: one of the
+/// registers, stack and pc, and one of everything printed. A slip in any
+/// opcode is all but certain to change one of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct VmFingerprint {
+    state: u64,
+    output: u64,
+}
+
+// FNV-1a, to match VM::state_hash and stay the same across Rust versions
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Run `program` on `input` until it asks for more input than there is, or
+/// until `max_cycles` are up.
+fn run_script(program: &[u8], input: &str, max_cycles: u64) -> (String, VmFingerprint) {
+    let mut vm = VM::load_program_from_reader(
+        Cursor::new(input.as_bytes().to_vec()),
+        Cursor::new(Vec::new()),
+        program,
+    )
+    .unwrap();
+
+    let (output, reason) = vm.run_capped(max_cycles);
+    assert_eq!(reason.unwrap(), StopReason::NeedsInput, "{}", output);
+
+    let fingerprint = VmFingerprint {
+        state: vm.state_hash(),
+        output: fnv1a(output.as_bytes()),
+    };
+    (output, fingerprint)
+}
+
+#[test]
+fn test_script_tablet() {
+    let (output, fingerprint) = run_script(CHALLENGE, "take tablet\nuse tablet\n", MAX_CYCLES);
+    assert!(output.contains("You find yourself writing"), "{}", output);
+    assert_eq!(
+        fingerprint,
+        VmFingerprint {
+            state: 13586106177903274875,
+            output: 5759967235837108332,
+        }
+    );
+}
+
+#[test]
+fn test_script_to_the_moss_cavern() {
+    let script = "doorway\nnorth\nnorth\nbridge\ncontinue\ndown\n";
+    let (output, fingerprint) = run_script(CHALLENGE, script, MAX_CYCLES);
+    assert!(output.contains("== Moss cavern =="), "{}", output);
+    assert_eq!(
+        fingerprint,
+        VmFingerprint {
+            state: 18333235033240993562,
+            output: 10426628777798183065,
+        }
+    );
+}
+
+#[test]
+fn test_rooms_paths_replay() {
+    let mut vm = VM::in_memory(CHALLENGE).unwrap();
+    let (_, start) = vm.cycle_until_next_room().unwrap();
+    let start = start.unwrap();
+
+    let rooms = explore::rooms(vm.clone(), start.clone())
+        .take(8)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(rooms.len(), 8);
+    assert_eq!(rooms[0], (vec![], start));
+
+    for (path, room) in rooms.into_iter().skip(1) {
+        let mut replay = vm.clone();
+        let mut reached = None;
+        for command in &path {
+            reached = replay.send_command(command).unwrap().1;
+        }
+        assert_eq!(reached.as_ref(), Some(&room), "{:?}", path);
+    }
+}